@@ -1,9 +1,30 @@
+import json
 import pyarrow as pa
 import pyarrow.flight as flight
 import grpc
+import numpy as np
+
+# dtype tag -> numpy dtype, matching blackhole::codec::DType (bf16 has no numpy type)
+RAW_DTYPES = {1: np.float32, 2: np.float16, 3: np.uint16, 4: np.int8}
+
+def decode_raw_value(value: bytes):
+    """
+    Split a raw value into (elements, scale). bf16 elements are returned as uint16 bit patterns;
+    scale is only set for int8 values.
+    """
+    assert value[:3] == b"BHV", "missing value header"
+    dtype_tag, big_endian = value[4], value[5] == 1
+    order = ">" if big_endian else "<"
+    payload = value[12:]
+    scale = None
+    if dtype_tag == 4:
+        scale = float(np.frombuffer(payload[:4], dtype=order + "f4")[0])
+        payload = payload[4:]
+    dtype = np.dtype(RAW_DTYPES[dtype_tag]).newbyteorder(order)
+    return np.frombuffer(payload, dtype=dtype), scale
 
 class FeatureClient:
-    def __init__(self, host="localhost", port=50051, wait_timeout=300):
+    def __init__(self, host="localhost", port=50051, wait_timeout=300, username=None, password=None):
         location = flight.Location.for_grpc_tcp(host, port)
         options = [
             ('grpc.enable_retries', 1),
@@ -17,23 +38,43 @@ class FeatureClient:
             }}')
         ]
         self.client = flight.connect(location, generic_options=options)
+        # servers started with a credentials file hand out a session token on handshake
+        self.options = flight.FlightCallOptions()
+        if username is not None:
+            token = self.client.authenticate_basic_token(username, password)
+            self.options = flight.FlightCallOptions(headers=[token])
     
-    def get_data(self, ids: list[str], features: list[tuple]):
+    def get_data(self, ids: list[str], features: list[tuple], raw: bool = False, flat: bool = False,
+                 strict: bool = False, missing_mask: bool = False, last_n: int = None):
         """
-        Retrieve data using a ticket containing feature tuples (name, start, end) and two scalar timestamps
+        Retrieve data using a ticket containing feature tuples (name, start, end) and two scalar timestamps.
+        A start or end of None reads from the earliest or up to the latest step; last_n keeps only the
+        newest last_n vectors of each range.
+        Batches hold one row per id, in request order, with an "id" column; each feature comes back as a
+        list of per-timestep vectors plus a "<name>_ts" list of timestamps.
+        flat=True returns the old layout: one batch per id with all timesteps concatenated into one float column.
+        With raw=True each value comes back as stored bytes; see decode_raw_value.
+        Missing (id, feature) pairs come back as nulls, and each batch's app_metadata holds a JSON list
+        of per-id statuses (read it with reader.read_chunk()); strict=True fails the request instead.
+        missing_mask=True adds a "<name>_missing" boolean column per feature.
         """
         # Create arrays
         ids_array = pa.array([ids], type=pa.list_(pa.string()))
         features_array = pa.array([features], type=pa.list_(pa.struct([
             ('name', pa.string()),
-            ('start', pa.int16()),
-            ('end', pa.int16())
+            ('start', pa.int64()),
+            ('end', pa.int64())
         ])))
         
         # Create struct array with proper types
+        raw_array = pa.array([raw], type=pa.bool_())
+        flat_array = pa.array([flat], type=pa.bool_())
+        strict_array = pa.array([strict], type=pa.bool_())
+        mask_array = pa.array([missing_mask], type=pa.bool_())
+        last_n_array = pa.array([last_n], type=pa.int64())
         struct_array = pa.StructArray.from_arrays(
-            [ids_array, features_array],
-            ['ids', 'features']
+            [ids_array, features_array, raw_array, flat_array, strict_array, mask_array, last_n_array],
+            ['ids', 'features', 'raw', 'flat', 'strict', 'missing_mask', 'last_n']
         )
         
         # Create a record batch with a single row (our struct)
@@ -47,4 +88,47 @@ class FeatureClient:
         
         # Create ticket with the serialized data
         ticket = flight.Ticket(sink.getvalue().to_pybytes())
-        return self.client.do_get(ticket) 
\ No newline at end of file
+        return self.client.do_get(ticket, self.options) 
+    def put_data(self, ids: list[str], features: list[str], timestamps: list[int], embeddings: np.ndarray):
+        """
+        Write one embedding row per (id, feature, timestamp) through do_put.
+        embeddings is a 2-D float array with one row per entry.
+        """
+        dim = embeddings.shape[1]
+        table = pa.table({
+            'id': pa.array(ids, type=pa.string()),
+            'feature': pa.array(features, type=pa.string()),
+            'ts': pa.array(timestamps, type=pa.uint64()),
+            'embedding': pa.FixedSizeListArray.from_arrays(
+                pa.array(embeddings.astype(np.float32).ravel()), dim),
+        })
+        writer, reader = self.client.do_put(flight.FlightDescriptor.for_path("embeddings"), table.schema, self.options)
+        writer.write_table(table)
+        writer.done_writing()
+        # one ack per committed batch
+        acks = []
+        while (ack := reader.read()) is not None:
+            acks.append(ack.to_pybytes())
+        writer.close()
+        return acks
+
+    def list_features(self, name_filter: str = ""):
+        """
+        Describe stored features whose name contains name_filter, as dicts of the catalog entries.
+        """
+        features = []
+        for info in self.client.list_flights(name_filter.encode(), self.options):
+            # the catalog entry rides on the values column, named after the feature
+            name = info.descriptor.path[0].decode()
+            field = info.schema.field(name)
+            entry = {k.decode(): v.decode() for k, v in field.metadata.items()}
+            features.append({'name': name, **entry})
+        return features
+
+    def admin(self, action: str, body: bytes = b""):
+        """
+        Run an admin action (stats, compact_range, flush, checkpoint, reader_check) and return its JSON result.
+        compact_range takes b"<start hex>:<end hex>"; checkpoint takes the target directory.
+        """
+        results = self.client.do_action(flight.Action(action, body), self.options)
+        return [json.loads(r.body.to_pybytes()) for r in results]
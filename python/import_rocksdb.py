@@ -4,14 +4,15 @@ import rocksdict
 import struct
 import numpy as np
 
+def encode_key(entity: str, feature: str, ts: int) -> bytes:
+    return entity.encode() + b"\0" + feature.encode() + b"\0" + struct.pack(">Q", ts)
+
 def create_sample_data(db_path: str):
     # Open Rocksdict
     options = rocksdict.Options(raw_mode=True)
     options.create_if_missing(True)
-    options.set_plain_table_factory(rocksdict.PlainTableFactoryOptions())
+    # block-based tables without a prefix extractor, matching the server defaults
     options.set_write_buffer_size(1024 * 1024 * 1024)
-    options.set_prefix_extractor(rocksdict.SliceTransform.create_fixed_prefix(10)) 
-    # options.set_prefix_extractor(rocksdict.SliceTransform.create_capped_prefix(10)) 
     options.set_compression_type(rocksdict.DBCompressionType.none())
     # Create DB with options
     db = rocksdict.Rdict(db_path, options)
@@ -32,9 +33,9 @@ def create_sample_data(db_path: str):
             # Store each embedding with an index
             # for idx in np.random.permutation(range(num_embeddings)):
             for idx in np.random.permutation(range(num_embeddings)):
-                # Key format: "{id}:{feature_name}:{index}"
+                # Key format matches BinaryKeyCodec: id \0 feature \0 big-endian u64 index
                 embedding = np.random.randn(dim).astype(np.float32)
-                key = f"{user_id}:{idx:04d}".encode()
+                key = encode_key(user_id, "", idx)
                 # Use tobytes() directly instead of float_to_bytes
                 value = embedding.tobytes()
                 db[key] = value
@@ -45,6 +45,8 @@ def main():
             reader = client.get_data(
                 ids, 
                 features,
+                flat=True,
+                strict=True,
             )
         
         # Read all batches from the stream
@@ -0,0 +1,35 @@
+/// Maps wall-clock time (milliseconds since the Unix epoch) onto the integer
+/// time slots used in keys, so callers can query by real time ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeBucketing {
+    origin_ms: u64,
+    width_ms: u64,
+}
+
+const HOUR_MS: u64 = 60 * 60 * 1000;
+
+impl TimeBucketing {
+    pub fn new(origin_ms: u64, width_ms: u64) -> Self {
+        assert!(width_ms > 0, "bucket width must be positive");
+        Self { origin_ms, width_ms }
+    }
+
+    pub fn hourly() -> Self {
+        Self::new(0, HOUR_MS)
+    }
+
+    pub fn daily() -> Self {
+        Self::new(0, 24 * HOUR_MS)
+    }
+
+    /// Slot containing `epoch_ms`; times before the origin fall into slot 0.
+    pub fn slot(&self, epoch_ms: i64) -> u64 {
+        let epoch_ms = u64::try_from(epoch_ms).unwrap_or(0);
+        epoch_ms.saturating_sub(self.origin_ms) / self.width_ms
+    }
+
+    /// First millisecond covered by `slot`.
+    pub fn slot_start_ms(&self, slot: u64) -> u64 {
+        self.origin_ms.saturating_add(slot.saturating_mul(self.width_ms))
+    }
+}
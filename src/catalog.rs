@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::codec::{DType, ValueHeader};
+use crate::{DbInterface, Error, Result};
+
+/// Catalog entries live in the same keyspace as the data, under a prefix that
+/// starts with 0xFF. Data keys are UTF-8 (binary or legacy codec), so they
+/// never start with 0xFF and always sort before the catalog.
+pub const CATALOG_PREFIX: &[u8] = b"\xFF__catalog__/";
+const FEATURE_TAG: &[u8] = b"feature/";
+const ENTITY_TAG: &[u8] = b"entity/";
+
+pub fn is_catalog_key(key: &[u8]) -> bool {
+    key.starts_with(CATALOG_PREFIX)
+}
+
+fn feature_key(feature: &str) -> Vec<u8> {
+    [CATALOG_PREFIX, FEATURE_TAG, feature.as_bytes()].concat()
+}
+
+fn entity_marker_key(feature: &str, entity: &str) -> Vec<u8> {
+    [CATALOG_PREFIX, ENTITY_TAG, feature.as_bytes(), b"\0", entity.as_bytes()].concat()
+}
+
+/// What the store holds for one feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureStats {
+    pub name: String,
+    pub dtype: DType,
+    pub dim: u32,
+    pub entity_count: u64,
+    pub key_count: u64,
+    pub min_ts: u64,
+    pub max_ts: u64,
+}
+
+// dtype tag, dim, entity_count, key_count, min_ts, max_ts
+const STATS_LEN: usize = 1 + 4 + 8 * 4;
+
+impl FeatureStats {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(STATS_LEN);
+        out.push(self.dtype.tag());
+        out.extend_from_slice(&self.dim.to_le_bytes());
+        for v in [self.entity_count, self.key_count, self.min_ts, self.max_ts] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out
+    }
+
+    fn from_bytes(name: &str, bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != STATS_LEN {
+            return Err(Error::Corruption(format!("catalog entry for {:?} has {} bytes", name, bytes.len())));
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[5 + 8 * i..13 + 8 * i].try_into().unwrap());
+        Ok(FeatureStats {
+            name: name.to_string(),
+            dtype: DType::from_tag(bytes[0])?,
+            dim: u32::from_le_bytes(bytes[1..5].try_into().unwrap()),
+            entity_count: u64_at(0),
+            key_count: u64_at(1),
+            min_ts: u64_at(2),
+            max_ts: u64_at(3),
+        })
+    }
+}
+
+/// Reads the catalog entry of `feature`, if anything was written for it.
+pub fn feature_stats(db: &dyn DbInterface, feature: &str) -> Result<Option<FeatureStats>> {
+    db.get(&feature_key(feature))?
+        .map(|bytes| FeatureStats::from_bytes(feature, &bytes))
+        .transpose()
+}
+
+/// Calls `visit` with the suffix and value of every key under `prefix`, in key order.
+fn scan_prefix(db: &dyn DbInterface, prefix: &[u8], mut visit: impl FnMut(&[u8], &[u8]) -> Result<()>) -> Result<()> {
+    let mut start = prefix.to_vec();
+    loop {
+        let page = db.scan(&start, 1024)?;
+        let Some((last, _)) = page.last() else {
+            return Ok(());
+        };
+        start = last.clone();
+        start.push(0);
+        for (key, value) in &page {
+            if !key.starts_with(prefix) {
+                return Ok(());
+            }
+            visit(&key[prefix.len()..], value)?;
+        }
+    }
+}
+
+fn utf8_name(bytes: &[u8]) -> Result<&str> {
+    std::str::from_utf8(bytes).map_err(|e| Error::Corruption(format!("catalog name is not UTF-8: {}", e)))
+}
+
+/// Lists every feature in the catalog, ordered by name.
+pub fn list_features(db: &dyn DbInterface) -> Result<Vec<FeatureStats>> {
+    let mut features = Vec::new();
+    scan_prefix(db, &feature_key(""), |name, value| {
+        features.push(FeatureStats::from_bytes(utf8_name(name)?, value)?);
+        Ok(())
+    })?;
+    Ok(features)
+}
+
+/// Lists the entities that have at least one key of `feature`, in key order.
+pub fn feature_entities(db: &dyn DbInterface, feature: &str) -> Result<Vec<String>> {
+    let mut entities = Vec::new();
+    scan_prefix(db, &entity_marker_key(feature, ""), |entity, _| {
+        entities.push(utf8_name(entity)?.to_string());
+        Ok(())
+    })?;
+    Ok(entities)
+}
+
+/// Computes the catalog entries to write together with `items`, so that data
+/// and catalog can be committed in one batch. Backends call this from every
+/// write path and must serialize calls, since stats are read, updated and
+/// written back.
+pub fn catalog_updates(db: &dyn DbInterface, items: &[(Vec<u8>, Vec<u8>)]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut stats: BTreeMap<String, FeatureStats> = BTreeMap::new();
+    let mut entities = BTreeSet::new();
+    let mut new_keys = HashSet::new();
+
+    for (key, value) in items {
+        if is_catalog_key(key) {
+            continue;
+        }
+        let (entity, feature, ts) = db.key_codec().decode(key)?;
+        // headerless values are legacy f32 arrays
+        let (dtype, dim) = match ValueHeader::read(value)? {
+            Some(header) => (header.dtype, header.dim),
+            None => (DType::F32, (value.len() / DType::F32.size()) as u32),
+        };
+        if !stats.contains_key(&feature) {
+            let existing = feature_stats(db, &feature)?.unwrap_or(FeatureStats {
+                name: feature.clone(),
+                dtype,
+                dim,
+                entity_count: 0,
+                key_count: 0,
+                min_ts: ts,
+                max_ts: ts,
+            });
+            stats.insert(feature.clone(), existing);
+        }
+        let entry = stats.get_mut(&feature).unwrap();
+        if entry.dim != dim {
+            return Err(Error::InvalidValue(format!(
+                "feature {:?} has dimension {}, got {}", feature, entry.dim, dim
+            )));
+        }
+        if entry.dtype != dtype {
+            return Err(Error::InvalidValue(format!(
+                "feature {:?} has dtype {:?}, got {:?}", feature, entry.dtype, dtype
+            )));
+        }
+        entry.min_ts = entry.min_ts.min(ts);
+        entry.max_ts = entry.max_ts.max(ts);
+        new_keys.insert(key.clone());
+        entities.insert((feature, entity));
+    }
+
+    // count only keys and entities the store has not seen before
+    let keys: Vec<&[u8]> = new_keys.iter().map(|k| k.as_slice()).collect();
+    for (key, exists) in keys.iter().zip(db.keys_exist(&keys)?) {
+        if !exists {
+            let (_, feature, _) = db.key_codec().decode(key)?;
+            stats.get_mut(&feature).unwrap().key_count += 1;
+        }
+    }
+    let markers: Vec<Vec<u8>> = entities.iter().map(|(f, e)| entity_marker_key(f, e)).collect();
+    let marker_refs: Vec<&[u8]> = markers.iter().map(|k| k.as_slice()).collect();
+    let mut updates = Vec::new();
+    for (((feature, _), marker), exists) in entities.iter().zip(&markers).zip(db.keys_exist(&marker_refs)?) {
+        if !exists {
+            stats.get_mut(feature).unwrap().entity_count += 1;
+            updates.push((marker.clone(), Vec::new()));
+        }
+    }
+    updates.extend(stats.values().map(|s| (feature_key(&s.name), s.to_bytes())));
+    Ok(updates)
+}
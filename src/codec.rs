@@ -0,0 +1,644 @@
+use std::collections::HashSet;
+
+use half::{bf16, f16};
+
+use crate::{DbInterface, Error, Result};
+
+/// Maps an (entity id, feature, timestamp) triple onto a storage key.
+///
+/// Implementations must keep all keys of one (entity, feature) pair under a
+/// common prefix and sort them by timestamp, in the direction given by
+/// `time_order`, so range scans stay contiguous.
+pub trait KeyCodec: Send + Sync {
+    fn encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>>;
+    fn decode(&self, key: &[u8]) -> Result<(String, String, u64)>;
+    /// Just the timestamp of `key`, for range scans that already know the prefix.
+    fn decode_ts(&self, key: &[u8]) -> Result<u64> {
+        Ok(self.decode(key)?.2)
+    }
+
+    fn reverse_encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>> {
+        self.encode(entity, feature, u64::MAX - ts)
+    }
+
+    /// Direction in which `encode` sorts the keys of `feature` by timestamp.
+    fn time_order(&self, _feature: &str) -> TimeOrder {
+        TimeOrder::Ascending
+    }
+
+    /// Lowest and highest key of `[start_ts, end_ts]` for (entity, feature), in key order.
+    fn key_range(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (start, end) = (self.encode(entity, feature, start_ts)?, self.encode(entity, feature, end_ts)?);
+        Ok(match self.time_order(feature) {
+            TimeOrder::Ascending => (start, end),
+            TimeOrder::Descending => (end, start),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeOrder {
+    Ascending,
+    /// Newest first, so the latest vectors are the first keys of their prefix.
+    Descending,
+}
+
+/// Lays out the listed features newest-first through the inner codec's
+/// `reverse_encode`; every other feature keeps the inner layout. Decoding
+/// maps timestamps back, so callers only ever see real timestamps.
+pub struct DescendingKeyCodec {
+    inner: Box<dyn KeyCodec>,
+    features: HashSet<String>,
+}
+
+impl DescendingKeyCodec {
+    pub fn new(inner: Box<dyn KeyCodec>, features: impl IntoIterator<Item = String>) -> Self {
+        Self { inner, features: features.into_iter().collect() }
+    }
+}
+
+impl KeyCodec for DescendingKeyCodec {
+    fn encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>> {
+        match self.time_order(feature) {
+            TimeOrder::Ascending => self.inner.encode(entity, feature, ts),
+            TimeOrder::Descending => self.inner.reverse_encode(entity, feature, ts),
+        }
+    }
+
+    fn decode(&self, key: &[u8]) -> Result<(String, String, u64)> {
+        let (entity, feature, ts) = self.inner.decode(key)?;
+        let ts = match self.time_order(&feature) {
+            TimeOrder::Ascending => ts,
+            TimeOrder::Descending => u64::MAX - ts,
+        };
+        Ok((entity, feature, ts))
+    }
+
+    fn reverse_encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>> {
+        self.inner.reverse_encode(entity, feature, ts)
+    }
+
+    fn time_order(&self, feature: &str) -> TimeOrder {
+        if self.features.contains(feature) {
+            TimeOrder::Descending
+        } else {
+            TimeOrder::Ascending
+        }
+    }
+}
+
+const SEPARATOR: u8 = 0;
+const TS_LEN: usize = std::mem::size_of::<u64>();
+
+/// Default codec: `entity \0 feature \0 ts` with the timestamp in big-endian
+/// so byte order matches numeric order across the full range.
+pub struct BinaryKeyCodec;
+
+impl KeyCodec for BinaryKeyCodec {
+    fn encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>> {
+        if entity.as_bytes().contains(&SEPARATOR) || feature.as_bytes().contains(&SEPARATOR) {
+            return Err(Error::InvalidKey(format!("NUL byte in entity {:?} or feature {:?}", entity, feature)));
+        }
+        let mut key = Vec::with_capacity(entity.len() + feature.len() + 2 + TS_LEN);
+        key.extend_from_slice(entity.as_bytes());
+        key.push(SEPARATOR);
+        key.extend_from_slice(feature.as_bytes());
+        key.push(SEPARATOR);
+        key.extend_from_slice(&ts.to_be_bytes());
+        Ok(key)
+    }
+
+    fn decode(&self, key: &[u8]) -> Result<(String, String, u64)> {
+        let invalid = || Error::InvalidKey(format!("malformed binary key {:?}", key));
+        if key.len() < TS_LEN + 2 {
+            return Err(invalid());
+        }
+        let (head, ts) = key.split_at(key.len() - TS_LEN);
+        if head[head.len() - 1] != SEPARATOR {
+            return Err(invalid());
+        }
+        let head = &head[..head.len() - 1];
+        let split = head.iter().position(|b| *b == SEPARATOR).ok_or_else(invalid)?;
+        let (entity, feature) = (&head[..split], &head[split + 1..]);
+        Ok((
+            String::from_utf8(entity.to_vec()).map_err(|_| invalid())?,
+            String::from_utf8(feature.to_vec()).map_err(|_| invalid())?,
+            u64::from_be_bytes(ts.try_into().map_err(|_| invalid())?),
+        ))
+    }
+
+    fn decode_ts(&self, key: &[u8]) -> Result<u64> {
+        let ts = key
+            .len()
+            .checked_sub(TS_LEN)
+            .map(|start| &key[start..])
+            .ok_or_else(|| Error::InvalidKey(format!("malformed binary key {:?}", key)))?;
+        Ok(u64::from_be_bytes(ts.try_into().unwrap()))
+    }
+}
+
+/// The original `{entity}.{feature}:{ts:04}` string layout, as written by
+/// `python/import_rocksdb.py` before the binary codec. Only sorts correctly
+/// for timestamps below 10000; kept so old data can be read and migrated.
+pub struct LegacyStringCodec;
+
+impl KeyCodec for LegacyStringCodec {
+    fn encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>> {
+        let key = if feature.is_empty() {
+            format!("{}:{:04}", entity, ts)
+        } else {
+            format!("{}.{}:{:04}", entity, feature, ts)
+        };
+        Ok(key.into_bytes())
+    }
+
+    fn decode(&self, key: &[u8]) -> Result<(String, String, u64)> {
+        let invalid = || Error::InvalidKey(format!("malformed legacy key {:?}", String::from_utf8_lossy(key)));
+        let key = std::str::from_utf8(key).map_err(|_| invalid())?;
+        let (prefix, ts) = key.rsplit_once(':').ok_or_else(invalid)?;
+        let ts = ts.parse::<u64>().map_err(|_| invalid())?;
+        let (entity, feature) = prefix.split_once('.').unwrap_or((prefix, ""));
+        Ok((entity.to_string(), feature.to_string(), ts))
+    }
+}
+
+/// Re-encodes a key written with `from` into the layout of `to`.
+pub fn migrate_key(from: &dyn KeyCodec, to: &dyn KeyCodec, key: &[u8]) -> Result<Vec<u8>> {
+    let (entity, feature, ts) = from.decode(key)?;
+    to.encode(&entity, &feature, ts)
+}
+
+/// Copies every entry of `src`, whose keys were written with `from`, into
+/// `dst` using `dst`'s own key codec, `batch_size` entries per write. Returns
+/// the number of entries copied.
+pub fn migrate_db(src: &dyn DbInterface, from: &dyn KeyCodec, dst: &dyn DbInterface, batch_size: usize) -> Result<usize> {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut copied = 0;
+    src.for_each_entry(&mut |key, value| {
+        // catalog entries are not keys of this codec; the destination keeps its own
+        if crate::catalog::is_catalog_key(key) {
+            return Ok(true);
+        }
+        batch.push((migrate_key(from, dst.key_codec(), key)?, value.to_vec()));
+        if batch.len() >= batch_size {
+            dst.batch_put(&batch)?;
+            copied += batch.len();
+            batch.clear();
+        }
+        Ok(true)
+    })?;
+    if !batch.is_empty() {
+        dst.batch_put(&batch)?;
+        copied += batch.len();
+    }
+    Ok(copied)
+}
+
+/// Element type of a stored vector.
+///
+/// `I8` is symmetric int8 quantization: each vector carries one f32 scale and
+/// element `i` dequantizes to `q[i] * scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    F32,
+    F16,
+    BF16,
+    I8,
+}
+
+impl DType {
+    pub fn size(&self) -> usize {
+        match self {
+            DType::F32 => 4,
+            DType::F16 | DType::BF16 => 2,
+            DType::I8 => 1,
+        }
+    }
+
+    /// Bytes stored per vector in addition to the elements.
+    fn extra(&self) -> usize {
+        match self {
+            DType::I8 => 4,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn tag(&self) -> u8 {
+        match self {
+            DType::F32 => 1,
+            DType::F16 => 2,
+            DType::BF16 => 3,
+            DType::I8 => 4,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            1 => Ok(DType::F32),
+            2 => Ok(DType::F16),
+            3 => Ok(DType::BF16),
+            4 => Ok(DType::I8),
+            _ => Err(Error::InvalidValue(format!("unknown dtype tag {}", tag))),
+        }
+    }
+}
+
+impl std::str::FromStr for DType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "f32" | "float32" => Ok(DType::F32),
+            "f16" | "float16" => Ok(DType::F16),
+            "bf16" | "bfloat16" => Ok(DType::BF16),
+            "i8" | "int8" => Ok(DType::I8),
+            _ => Err(Error::InvalidValue(format!("unknown dtype {:?}", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for DType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DType::F32 => "f32",
+            DType::F16 => "f16",
+            DType::BF16 => "bf16",
+            DType::I8 => "i8",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    fn tag(&self) -> u8 {
+        match self {
+            Endianness::Little => 0,
+            Endianness::Big => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(Endianness::Little),
+            1 => Ok(Endianness::Big),
+            _ => Err(Error::InvalidValue(format!("unknown endianness tag {}", tag))),
+        }
+    }
+}
+
+/// Header stored in front of every encoded vector.
+///
+/// Layout (12 bytes): magic `BHV`, version, dtype, endianness, two reserved
+/// bytes, then the dimension as a little-endian u32. For `I8` the payload
+/// starts with the f32 scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueHeader {
+    pub dtype: DType,
+    pub endianness: Endianness,
+    pub dim: u32,
+}
+
+const VALUE_MAGIC: &[u8; 3] = b"BHV";
+const VALUE_VERSION: u8 = 1;
+pub const VALUE_HEADER_LEN: usize = 12;
+
+impl ValueHeader {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(VALUE_MAGIC);
+        out.extend_from_slice(&[VALUE_VERSION, self.dtype.tag(), self.endianness.tag(), 0, 0]);
+        out.extend_from_slice(&self.dim.to_le_bytes());
+    }
+
+    /// Parses the header, or returns `None` for values written without one
+    /// (e.g. raw numpy `tobytes()` from `python/import_rocksdb.py`). A legacy
+    /// f32 array can start with the magic bytes by chance, so the bytes only
+    /// count as a header if it parses and describes exactly the rest of the value.
+    pub fn read(bytes: &[u8]) -> Result<Option<Self>> {
+        if bytes.len() < VALUE_HEADER_LEN || &bytes[..3] != VALUE_MAGIC || bytes[3] != VALUE_VERSION {
+            return Ok(None);
+        }
+        let (Ok(dtype), Ok(endianness)) = (DType::from_tag(bytes[4]), Endianness::from_tag(bytes[5])) else {
+            return Ok(None);
+        };
+        let header = ValueHeader { dtype, endianness, dim: u32::from_le_bytes(bytes[8..12].try_into().unwrap()) };
+        Ok((VALUE_HEADER_LEN + header.payload_len() == bytes.len()).then_some(header))
+    }
+
+    fn payload_len(&self) -> usize {
+        self.dim as usize * self.dtype.size() + self.dtype.extra()
+    }
+}
+
+/// A vector in its stored element type, before dequantization.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawVector {
+    F32(Vec<f32>),
+    F16(Vec<f16>),
+    BF16(Vec<bf16>),
+    I8 { values: Vec<i8>, scale: f32 },
+}
+
+macro_rules! put_elems {
+    ($out:expr, $values:expr, $endianness:expr) => {
+        for v in $values {
+            match $endianness {
+                Endianness::Little => $out.extend_from_slice(&v.to_le_bytes()),
+                Endianness::Big => $out.extend_from_slice(&v.to_be_bytes()),
+            }
+        }
+    };
+}
+
+macro_rules! get_elems {
+    ($ty:ty, $payload:expr, $endianness:expr) => {
+        $payload
+            .chunks_exact(std::mem::size_of::<$ty>())
+            .map(|chunk| {
+                let chunk = chunk.try_into().unwrap();
+                match $endianness {
+                    Endianness::Little => <$ty>::from_le_bytes(chunk),
+                    Endianness::Big => <$ty>::from_be_bytes(chunk),
+                }
+            })
+            .collect()
+    };
+}
+
+impl RawVector {
+    pub fn quantize(values: &[f32], dtype: DType) -> Self {
+        match dtype {
+            DType::F32 => RawVector::F32(values.to_vec()),
+            DType::F16 => RawVector::F16(values.iter().map(|v| f16::from_f32(*v)).collect()),
+            DType::BF16 => RawVector::BF16(values.iter().map(|v| bf16::from_f32(*v)).collect()),
+            DType::I8 => {
+                let max_abs = values.iter().fold(0f32, |m, v| m.max(v.abs()));
+                let scale = if max_abs > 0.0 { max_abs / i8::MAX as f32 } else { 1.0 };
+                let values = values
+                    .iter()
+                    .map(|v| (v / scale).round().clamp(-(i8::MAX as f32), i8::MAX as f32) as i8)
+                    .collect();
+                RawVector::I8 { values, scale }
+            }
+        }
+    }
+
+    pub fn dtype(&self) -> DType {
+        match self {
+            RawVector::F32(_) => DType::F32,
+            RawVector::F16(_) => DType::F16,
+            RawVector::BF16(_) => DType::BF16,
+            RawVector::I8 { .. } => DType::I8,
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        match self {
+            RawVector::F32(v) => v.len(),
+            RawVector::F16(v) => v.len(),
+            RawVector::BF16(v) => v.len(),
+            RawVector::I8 { values, .. } => values.len(),
+        }
+    }
+
+    pub fn dequantize(&self) -> Vec<f32> {
+        match self {
+            RawVector::F32(v) => v.clone(),
+            RawVector::F16(v) => v.iter().map(|v| v.to_f32()).collect(),
+            RawVector::BF16(v) => v.iter().map(|v| v.to_f32()).collect(),
+            RawVector::I8 { values, scale } => values.iter().map(|q| *q as f32 * scale).collect(),
+        }
+    }
+
+    /// Serializes the vector with its `ValueHeader`.
+    pub fn to_bytes(&self, endianness: Endianness) -> Result<Vec<u8>> {
+        let dim = u32::try_from(self.dim())
+            .map_err(|_| Error::InvalidValue(format!("dimension {} too large", self.dim())))?;
+        let header = ValueHeader { dtype: self.dtype(), endianness, dim };
+        let mut out = Vec::with_capacity(VALUE_HEADER_LEN + header.payload_len());
+        header.write(&mut out);
+        match self {
+            RawVector::F32(v) => put_elems!(out, v, endianness),
+            RawVector::F16(v) => put_elems!(out, v, endianness),
+            RawVector::BF16(v) => put_elems!(out, v, endianness),
+            RawVector::I8 { values, scale } => {
+                put_elems!(out, [scale], endianness);
+                out.extend(values.iter().map(|q| *q as u8));
+            }
+        }
+        Ok(out)
+    }
+
+    fn from_payload(header: &ValueHeader, payload: &[u8]) -> Result<Self> {
+        if payload.len() != header.payload_len() {
+            return Err(Error::InvalidValue(format!(
+                "payload of {} bytes does not match {} x {:?}", payload.len(), header.dim, header.dtype
+            )));
+        }
+        Ok(match header.dtype {
+            DType::F32 => RawVector::F32(get_elems!(f32, payload, header.endianness)),
+            DType::F16 => RawVector::F16(get_elems!(f16, payload, header.endianness)),
+            DType::BF16 => RawVector::BF16(get_elems!(bf16, payload, header.endianness)),
+            DType::I8 => {
+                let (scale, values) = payload.split_at(4);
+                let scale: Vec<f32> = get_elems!(f32, scale, header.endianness);
+                RawVector::I8 { values: values.iter().map(|q| *q as i8).collect(), scale: scale[0] }
+            }
+        })
+    }
+}
+
+/// Serializes embeddings to and from stored values.
+pub trait ValueCodec: Send + Sync {
+    fn encode(&self, values: &[f32]) -> Result<Vec<u8>>;
+    /// Decodes a stored value without dequantizing it.
+    fn decode_raw(&self, bytes: &[u8]) -> Result<RawVector>;
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<f32>> {
+        Ok(self.decode_raw(bytes)?.dequantize())
+    }
+}
+
+/// Default value codec: a `ValueHeader` followed by the packed elements.
+///
+/// `dtype` is the storage type used on write; reads accept any dtype named in
+/// the header. Values without a header are read as legacy little-endian f32
+/// arrays. When `dim` is set, vectors of any other dimension are rejected.
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderValueCodec {
+    pub dtype: DType,
+    pub endianness: Endianness,
+    pub dim: Option<u32>,
+}
+
+impl Default for HeaderValueCodec {
+    fn default() -> Self {
+        Self { dtype: DType::F32, endianness: Endianness::Little, dim: None }
+    }
+}
+
+impl HeaderValueCodec {
+    fn check_dim(&self, dim: usize) -> Result<()> {
+        match self.dim {
+            Some(expected) if expected as usize != dim => Err(Error::InvalidValue(format!(
+                "expected dimension {}, got {}", expected, dim
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ValueCodec for HeaderValueCodec {
+    fn encode(&self, values: &[f32]) -> Result<Vec<u8>> {
+        self.check_dim(values.len())?;
+        RawVector::quantize(values, self.dtype).to_bytes(self.endianness)
+    }
+
+    fn decode_raw(&self, bytes: &[u8]) -> Result<RawVector> {
+        let (header, payload) = match ValueHeader::read(bytes)? {
+            Some(header) => (header, &bytes[VALUE_HEADER_LEN..]),
+            None => {
+                if !bytes.len().is_multiple_of(DType::F32.size()) {
+                    return Err(Error::InvalidValue(format!(
+                        "legacy value of {} bytes is not a whole number of f32", bytes.len()
+                    )));
+                }
+                let dim = (bytes.len() / DType::F32.size()) as u32;
+                (ValueHeader { dtype: DType::F32, endianness: Endianness::Little, dim }, bytes)
+            }
+        };
+        self.check_dim(header.dim as usize)?;
+        RawVector::from_payload(&header, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EDGE_TS: [u64; 7] = [0, 1, 9_999, 10_000, u32::MAX as u64 + 1, u64::MAX - 1, u64::MAX];
+
+    fn sorted_keys(codec: &dyn KeyCodec, entity: &str, feature: &str) -> Vec<u64> {
+        let mut keys: Vec<Vec<u8>> = EDGE_TS.iter().map(|ts| codec.encode(entity, feature, *ts).unwrap()).collect();
+        keys.sort();
+        keys.iter().map(|key| codec.decode_ts(key).unwrap()).collect()
+    }
+
+    #[test]
+    fn binary_keys_round_trip() {
+        for ts in EDGE_TS {
+            let key = BinaryKeyCodec.encode("u1", "emb", ts).unwrap();
+            assert_eq!(BinaryKeyCodec.decode(&key).unwrap(), ("u1".to_string(), "emb".to_string(), ts));
+            assert_eq!(BinaryKeyCodec.decode_ts(&key).unwrap(), ts);
+        }
+        assert!(BinaryKeyCodec.encode("u\0x", "emb", 0).is_err());
+    }
+
+    #[test]
+    fn binary_keys_sort_by_timestamp() {
+        assert_eq!(sorted_keys(&BinaryKeyCodec, "u1", "emb"), EDGE_TS);
+        // a shorter entity that prefixes a longer one keeps its keys together
+        let short = BinaryKeyCodec.encode("u1", "emb", u64::MAX).unwrap();
+        let long = BinaryKeyCodec.encode("u10", "emb", 0).unwrap();
+        assert!(short < long);
+    }
+
+    #[test]
+    fn legacy_keys_round_trip_and_migrate_in_order() {
+        let legacy = LegacyStringCodec;
+        for ts in EDGE_TS {
+            let key = legacy.encode("u1", "emb", ts).unwrap();
+            assert_eq!(legacy.decode(&key).unwrap(), ("u1".to_string(), "emb".to_string(), ts));
+        }
+        // the string layout puts 10000 before 9999; migrated keys sort numerically
+        let (before, after) = (legacy.encode("u1", "emb", 9_999).unwrap(), legacy.encode("u1", "emb", 10_000).unwrap());
+        assert!(after < before);
+        let migrated = (
+            migrate_key(&legacy, &BinaryKeyCodec, &before).unwrap(),
+            migrate_key(&legacy, &BinaryKeyCodec, &after).unwrap(),
+        );
+        assert!(migrated.0 < migrated.1);
+    }
+
+    #[test]
+    fn migrates_legacy_lmdb_store() {
+        use crate::config::{BackendConfig, KeyFormat};
+        let dir = std::env::temp_dir().join(format!("blackhole-migrate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let open = |name: &str, key_format| {
+            let mut config = BackendConfig::new(dir.join(name));
+            config.key_format = key_format;
+            config.lmdb.map_size = 1 << 24;
+            crate::lmdb::open_lmdb(&config).unwrap()
+        };
+        let (src, dst) = (open("legacy", KeyFormat::Legacy), open("binary", KeyFormat::Binary));
+        let items: Vec<_> = [("u1", 10_000), ("u1", 9_999), ("u1", 1), ("u2", 5), ("u2", u64::MAX)]
+            .iter()
+            .map(|(entity, ts)| {
+                let value = src.value_codec().encode(&[*ts as f32, 0.0]).unwrap();
+                (src.key_codec().encode(entity, "emb", *ts).unwrap(), value)
+            })
+            .collect();
+        src.batch_put(&items).unwrap();
+
+        // two entries per batch, so the last batch is partial
+        assert_eq!(migrate_db(src.as_ref(), &LegacyStringCodec, dst.as_ref(), 2).unwrap(), items.len());
+        let timestamps = |entity| -> Vec<u64> {
+            dst.prefix_seek_timed(entity, "emb", 0, u64::MAX).unwrap().into_iter().map(|(ts, _)| ts).collect()
+        };
+        assert_eq!(timestamps("u1"), [1, 9_999, 10_000]);
+        assert_eq!(timestamps("u2"), [5, u64::MAX]);
+        let stats = crate::catalog::feature_stats(dst.as_ref(), "emb").unwrap().unwrap();
+        assert_eq!((stats.entity_count, stats.key_count), (2, 5));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn descending_keys_round_trip_newest_first() {
+        let codec = DescendingKeyCodec::new(Box::new(BinaryKeyCodec), ["emb".to_string()]);
+        for ts in EDGE_TS {
+            let key = codec.encode("u1", "emb", ts).unwrap();
+            assert_eq!(codec.decode(&key).unwrap(), ("u1".to_string(), "emb".to_string(), ts));
+            assert_eq!(codec.decode_ts(&key).unwrap(), ts);
+        }
+        let mut newest_first = EDGE_TS;
+        newest_first.reverse();
+        assert_eq!(sorted_keys(&codec, "u1", "emb"), newest_first);
+        // other features keep the inner layout
+        assert_eq!(sorted_keys(&codec, "u1", "other"), EDGE_TS);
+
+        let (low, high) = codec.key_range("u1", "emb", 9_999, 10_000).unwrap();
+        assert!(low < high);
+        assert_eq!(codec.decode_ts(&low).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn legacy_value_starting_with_magic_stays_legacy() {
+        // five f32 whose first 12 bytes read as a version-1 F32 header of dim 8
+        let mut bytes = b"BHV\x01\x01\x00\x00\x00\x08\x00\x00\x00".to_vec();
+        bytes.extend_from_slice(&1.5f32.to_le_bytes());
+        bytes.extend_from_slice(&2.5f32.to_le_bytes());
+        assert_eq!(ValueHeader::read(&bytes).unwrap(), None);
+
+        let expected: Vec<u32> = bytes.chunks(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+        let decoded = HeaderValueCodec::default().decode(&bytes).unwrap();
+        assert_eq!(decoded.iter().map(|v| v.to_bits()).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn headered_values_round_trip() {
+        let values = [0.5f32, -1.25, 3.0];
+        for dtype in [DType::F32, DType::F16, DType::BF16, DType::I8] {
+            let codec = HeaderValueCodec { dtype, ..Default::default() };
+            let bytes = codec.encode(&values).unwrap();
+            let header = ValueHeader::read(&bytes).unwrap().unwrap();
+            assert_eq!((header.dtype, header.dim), (dtype, 3));
+            let decoded = codec.decode(&bytes).unwrap();
+            assert!(decoded.iter().zip(values).all(|(a, b)| (a - b).abs() < 0.02), "{:?}: {:?}", dtype, decoded);
+        }
+    }
+}
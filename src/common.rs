@@ -3,7 +3,7 @@ use std::sync::Arc;
 use std::thread;
 use criterion::{BenchmarkId, Criterion};
 use rand::Rng;
-use crate::DbInterface;
+use crate::{DbInterface, ValueCodec};
 use std::time::{Duration, Instant};
 use std::sync::atomic::AtomicU64;
 use histogram::Histogram;
@@ -21,14 +21,14 @@ pub fn writer_thread(db: Arc<Box<dyn DbInterface>>, should_stop: Arc<AtomicBool>
     let mut batch = vec![];
     let mut keys = vec![];
     while !should_stop.load(Ordering::Relaxed) && idx < NUM_KEYS {
-        let key = format!("{}_{:010}", key_prefix, idx).into_bytes();
+        let key = db.encode(key_prefix, "bench", idx as u64).expect("Failed to encode key");
         keys.push(key.clone());
          if batch.len() == write_batch_size {
             db.batch_put(&batch).expect("Failed to batch put");
             batch.clear();
         }
         else {
-            batch.push((key, generate_random_embedding()));
+            batch.push((key, generate_random_embedding(db.value_codec())));
         }
         idx += 1;
     }
@@ -71,7 +71,8 @@ pub fn bench_reads_under_write(c: &mut Criterion, db: Box<dyn DbInterface>) {
                     not_found += 1;
                 }
                 else {
-                    assert_eq!(v.unwrap().len(), EMBEDDING_SIZE*4);
+                    let embedding = db.value_codec().decode(&v.unwrap()).expect("Decode failed");
+                    assert_eq!(embedding.len(), EMBEDDING_SIZE);
                 }
             }
             if not_found > 0 {
@@ -94,6 +95,17 @@ pub fn bench_reads_under_write(c: &mut Criterion, db: Box<dyn DbInterface>) {
         });
     });
     
+    // Benchmark random reads served by a single multi_get call
+    group.bench_function(BenchmarkId::new("multi_get_reads", ""), |b| {
+        b.iter(|| {
+            let mut rng = rand::thread_rng();
+            let batch: Vec<&[u8]> = (0..READ_BATCH)
+                .map(|_| sequential_keys[rng.gen_range(0..sequential_keys.len())].as_slice())
+                .collect();
+            db.multi_get(&batch).expect("Read failed");
+        });
+    });
+
     // Benchmark multi-threaded reads
     let num_threads = 8;
     group.bench_function(BenchmarkId::new("parallel_reads", ""), |b| {
@@ -125,10 +137,10 @@ pub fn bench_reads_under_write(c: &mut Criterion, db: Box<dyn DbInterface>) {
     group.finish();
 } 
 
-pub fn generate_random_embedding() -> Vec<u8> {
+pub fn generate_random_embedding(codec: &dyn ValueCodec) -> Vec<u8> {
     let mut rng = rand::thread_rng();
     let embeddings: Vec<f32> = (0..EMBEDDING_SIZE).map(|_| rng.gen::<f32>()).collect();
-    embeddings.into_iter().map(|f| f.to_ne_bytes()).flatten().collect()
+    codec.encode(&embeddings).expect("Failed to encode embedding")
 } 
 
 #[derive(Debug)]
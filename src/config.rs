@@ -0,0 +1,208 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::codec::{BinaryKeyCodec, DType, DescendingKeyCodec, Endianness, HeaderValueCodec, LegacyStringCodec};
+use crate::{Error, KeyCodec, Result};
+
+/// Everything needed to open a backend, so one binary can serve different
+/// datasets without recompiling. Loadable from TOML; omitted fields keep
+/// their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    pub path: PathBuf,
+    /// Open without write access. Unset means writable, except in the Flight
+    /// server, which serves read-only unless told otherwise.
+    pub read_only: Option<bool>,
+    pub create_if_missing: bool,
+    pub key_format: KeyFormat,
+    /// Features whose keys are stored newest-first, so "latest N" reads are a
+    /// single forward seek. Changing this for a feature that already has data
+    /// makes that data unreadable until it is migrated.
+    pub descending_features: Vec<String>,
+    pub values: ValueConfig,
+    pub rocksdb: RocksDbConfig,
+    pub lmdb: LmdbConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyFormat {
+    Binary,
+    Legacy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValueConfig {
+    /// Storage dtype used on write: f32, f16, bf16 or i8.
+    pub dtype: String,
+    pub big_endian: bool,
+    pub dim: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TableFormat {
+    Plain,
+    BlockBased,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RocksDbConfig {
+    pub table_format: TableFormat,
+    /// Length of the fixed-size prefix extractor; `None` disables it.
+    pub prefix_len: Option<usize>,
+    pub write_buffer_size: usize,
+    pub max_write_buffer_number: i32,
+    pub max_background_jobs: i32,
+    /// Block cache for the block-based table format; 0 leaves RocksDB's default.
+    pub block_cache_size: usize,
+    /// Row cache shared by all table formats; 0 disables it.
+    pub row_cache_size: usize,
+    pub bloom_bits_per_key: i32,
+    /// Open as a secondary of the primary at `path`, keeping secondary state here.
+    pub secondary_path: Option<PathBuf>,
+    pub catch_up_interval_ms: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LmdbConfig {
+    pub map_size: usize,
+    pub max_dbs: u32,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("./test.db"),
+            read_only: None,
+            create_if_missing: true,
+            key_format: KeyFormat::Binary,
+            descending_features: Vec::new(),
+            values: ValueConfig::default(),
+            rocksdb: RocksDbConfig::default(),
+            lmdb: LmdbConfig::default(),
+        }
+    }
+}
+
+impl Default for ValueConfig {
+    fn default() -> Self {
+        Self { dtype: "f32".to_string(), big_endian: false, dim: None }
+    }
+}
+
+impl Default for RocksDbConfig {
+    fn default() -> Self {
+        Self {
+            table_format: TableFormat::BlockBased,
+            prefix_len: None,
+            write_buffer_size: 64 * 1024 * 1024,
+            max_write_buffer_number: 2,
+            max_background_jobs: 2,
+            block_cache_size: 0,
+            row_cache_size: 0,
+            bloom_bits_per_key: 10,
+            secondary_path: None,
+            catch_up_interval_ms: 1000,
+        }
+    }
+}
+
+impl Default for LmdbConfig {
+    fn default() -> Self {
+        Self { map_size: 80 * 1024_usize.pow(3), max_dbs: 1 }
+    }
+}
+
+impl BackendConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), ..Default::default() }
+    }
+
+    pub fn from_toml_str(s: &str) -> Result<Self> {
+        let config: Self = toml::from_str(s).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("cannot read {}: {}", path.display(), e)))?;
+        Self::from_toml_str(&s)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Err(Error::InvalidConfig("path must not be empty".to_string()));
+        }
+        self.values.dtype.parse::<DType>().map_err(|e| Error::InvalidConfig(format!("values.dtype: {}", e)))?;
+        if self.values.dim == Some(0) {
+            return Err(Error::InvalidConfig("values.dim must be positive".to_string()));
+        }
+        if self.rocksdb.prefix_len == Some(0) {
+            return Err(Error::InvalidConfig("rocksdb.prefix_len must be positive".to_string()));
+        }
+        if self.rocksdb.table_format == TableFormat::Plain && self.rocksdb.prefix_len.is_none() {
+            return Err(Error::InvalidConfig("rocksdb.table_format = \"plain\" requires rocksdb.prefix_len".to_string()));
+        }
+        // binary keys start with a variable-length entity, so a fixed-length
+        // prefix cuts (entity, feature) prefixes at arbitrary points
+        if self.key_format == KeyFormat::Binary && self.rocksdb.prefix_len.is_some() {
+            return Err(Error::InvalidConfig(
+                "rocksdb.prefix_len does not fit the variable-length binary key format; use table_format = \"block_based\" without prefix_len".to_string(),
+            ));
+        }
+        if self.rocksdb.secondary_path.is_some() && self.rocksdb.catch_up_interval_ms == 0 {
+            return Err(Error::InvalidConfig("rocksdb.catch_up_interval_ms must be positive".to_string()));
+        }
+        if self.lmdb.map_size == 0 {
+            return Err(Error::InvalidConfig("lmdb.map_size must be positive".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.unwrap_or(false)
+    }
+
+    pub fn key_codec(&self) -> Box<dyn KeyCodec> {
+        let codec: Box<dyn KeyCodec> = match self.key_format {
+            KeyFormat::Binary => Box::new(BinaryKeyCodec),
+            KeyFormat::Legacy => Box::new(LegacyStringCodec),
+        };
+        if self.descending_features.is_empty() {
+            return codec;
+        }
+        Box::new(DescendingKeyCodec::new(codec, self.descending_features.iter().cloned()))
+    }
+
+    pub fn value_codec(&self) -> Result<HeaderValueCodec> {
+        Ok(HeaderValueCodec {
+            dtype: self.values.dtype.parse()?,
+            endianness: if self.values.big_endian { Endianness::Big } else { Endianness::Little },
+            dim: self.values.dim,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_prefix_needs_legacy_keys() {
+        let mut config = BackendConfig::new("./test.db");
+        config.rocksdb.prefix_len = Some(10);
+        assert!(config.validate().is_err());
+        config.key_format = KeyFormat::Legacy;
+        config.validate().unwrap();
+        assert!(BackendConfig::from_toml_str("[rocksdb]\ntable_format = \"plain\"\nprefix_len = 10").is_err());
+        BackendConfig::from_toml_str(&std::fs::read_to_string("backend.toml").unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+use tonic::Status;
+
+/// Errors surfaced by every `DbInterface` backend.
+///
+/// Backends translate their native errors into one of these variants so the
+/// Flight server can map them onto a stable set of gRPC status codes.
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    Corruption(String),
+    MapFull(String),
+    IoError(String),
+    InvalidKey(String),
+    InvalidValue(String),
+    InvalidConfig(String),
+    /// A write was attempted on a backend opened read-only.
+    ReadOnly(String),
+    /// The backend does not implement an optional operation.
+    Unsupported(String),
+    BackendSpecific { backend: &'static str, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Whether retrying the same request later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::IoError(_) | Error::MapFull(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(msg) => write!(f, "not found: {}", msg),
+            Error::Corruption(msg) => write!(f, "corruption: {}", msg),
+            Error::MapFull(msg) => write!(f, "map full: {}", msg),
+            Error::IoError(msg) => write!(f, "io error: {}", msg),
+            Error::InvalidKey(msg) => write!(f, "invalid key: {}", msg),
+            Error::InvalidValue(msg) => write!(f, "invalid value: {}", msg),
+            Error::InvalidConfig(msg) => write!(f, "invalid config: {}", msg),
+            Error::ReadOnly(msg) => write!(f, "read-only: {}", msg),
+            Error::Unsupported(msg) => write!(f, "unsupported: {}", msg),
+            Error::BackendSpecific { backend, message } => write!(f, "{} error: {}", backend, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::IoError(e.to_string())
+    }
+}
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        let msg = e.to_string();
+        match e {
+            Error::NotFound(_) => Status::not_found(msg),
+            Error::Corruption(_) => Status::data_loss(msg),
+            Error::MapFull(_) => Status::resource_exhausted(msg),
+            Error::IoError(_) => Status::unavailable(msg),
+            Error::InvalidKey(_) | Error::InvalidValue(_) => Status::invalid_argument(msg),
+            Error::InvalidConfig(_) | Error::ReadOnly(_) => Status::failed_precondition(msg),
+            Error::Unsupported(_) => Status::unimplemented(msg),
+            Error::BackendSpecific { .. } => Status::internal(msg),
+        }
+    }
+}
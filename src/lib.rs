@@ -1,37 +1,110 @@
 pub mod lmdb;
 pub mod rocksdb;
+pub mod bucketing;
+pub mod catalog;
 pub mod common;
+pub mod codec;
+pub mod config;
+pub mod error;
+
+pub use bucketing::TimeBucketing;
+pub use catalog::FeatureStats;
+pub use codec::{KeyCodec, RawVector, TimeOrder, ValueCodec};
+pub use config::BackendConfig;
+pub use error::{Error, Result};
 pub enum DatabaseType {
     RocksDB,
     LMDB,
 }
 
 impl DatabaseType {
-    pub fn create_db(&self) -> Box<dyn DbInterface> {
+    pub fn create_db(&self, config: &BackendConfig) -> Result<Box<dyn DbInterface>> {
         match self {
-            DatabaseType::RocksDB => rocksdb::open_rocks_readonly(),
-            DatabaseType::LMDB => lmdb::setup_lmdb(),
+            DatabaseType::RocksDB => rocksdb::open_rocks(config),
+            DatabaseType::LMDB => lmdb::open_lmdb(config),
         }
     }
 }
+/// Callback of `DbInterface::for_each_entry`; returning false stops the walk.
+pub type EntryVisitor<'a> = dyn FnMut(&[u8], &[u8]) -> Result<bool> + 'a;
+
 pub trait DbInterface: Send + Sync {
     fn db_type(&self) -> String;
-    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Box<dyn std::error::Error>>;
-    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>>;
-    fn batch_put(&self, items: &[(Vec<u8>, Vec<u8>)]) -> Result<(), Box<dyn std::error::Error>>;
-    fn close(&self) -> Result<(), Box<dyn std::error::Error>>;
-    fn prefix_seek(&self, prefix: &str, start_ts: u16, end_ts: u16) -> Result<Vec<f32>, Box<dyn std::error::Error>>;
-    
-    fn reverse_encode(&self, prefix: &str, ts: u16) -> String {
-        format!("{}:{:04}", prefix, u16::MAX - ts)
-    }
-    fn encode(&self, prefix: &str, ts: u16) -> String {
-        format!("{}:{:04}", prefix, ts)
-    }
-    fn numpy_f32_vec(&self, bytes: &[u8]) -> Vec<f32> {
-        bytes
-            .chunks_exact(4)
-            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
-            .collect()
+    /// Whether the backend was opened without write access; writes then fail with `Error::ReadOnly`.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+    /// Time since a follower last caught up with its primary; `None` if the backend is not a follower.
+    fn replication_lag(&self) -> Option<std::time::Duration> {
+        None
+    }
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()>;
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// Fetches several keys at once, returning values in the order of `keys`.
+    fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>>;
+    /// Reports which of `keys` are present, in the order of `keys`, without copying values.
+    fn keys_exist(&self, keys: &[&[u8]]) -> Result<Vec<bool>>;
+    fn batch_put(&self, items: &[(Vec<u8>, Vec<u8>)]) -> Result<()>;
+    /// Returns up to `limit` entries with keys >= `start`, in key order.
+    fn scan(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+    /// Calls `visit` with every entry in key order, in one pass, until it
+    /// returns false. Unlike `scan`, this never depends on prefix seeks.
+    fn for_each_entry(&self, visit: &mut EntryVisitor<'_>) -> Result<()>;
+    /// Persists outstanding writes before shutdown; reads may still be served afterwards.
+    fn close(&self) -> Result<()>;
+    /// Returns the stored vectors of (entity, feature) in `[start_ts, end_ts]` with
+    /// their timestamps, oldest first and without dequantizing. The scan follows
+    /// the feature's `KeyCodec::time_order`.
+    fn prefix_seek_timed(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64) -> Result<Vec<(u64, RawVector)>>;
+    /// The newest `n` vectors of `prefix_seek_timed`'s range, returned oldest
+    /// first. Walks back from `end_ts`, or forward in a descending layout.
+    fn prefix_seek_last(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64, n: usize) -> Result<Vec<(u64, RawVector)>>;
+    fn key_codec(&self) -> &dyn KeyCodec;
+    fn value_codec(&self) -> &dyn ValueCodec;
+
+    /// Same range as `prefix_seek_timed`, without the timestamps.
+    fn prefix_seek_raw(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64) -> Result<Vec<RawVector>> {
+        Ok(self
+            .prefix_seek_timed(entity, feature, start_ts, end_ts)?
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect())
+    }
+
+    /// Same range as `prefix_seek_raw`, dequantized to f32 and flattened.
+    fn prefix_seek(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64) -> Result<Vec<f32>> {
+        Ok(self
+            .prefix_seek_raw(entity, feature, start_ts, end_ts)?
+            .iter()
+            .flat_map(|v| v.dequantize())
+            .collect())
+    }
+
+    /// Backend counters for operators, as (name, value) pairs.
+    fn stats(&self) -> Result<Vec<(String, String)>> {
+        Err(Error::Unsupported(format!("{} does not report stats", self.db_type())))
+    }
+    /// Compacts keys in `[start, end]`; `None` leaves that side unbounded.
+    fn compact_range(&self, _start: Option<&[u8]>, _end: Option<&[u8]>) -> Result<()> {
+        Err(Error::Unsupported(format!("{} does not support compaction", self.db_type())))
+    }
+    /// Persists buffered writes to disk.
+    fn flush(&self) -> Result<()> {
+        Err(Error::Unsupported(format!("{} does not support flush", self.db_type())))
+    }
+    /// Writes a consistent copy of the store into `dir`, which must not exist yet.
+    fn checkpoint(&self, _dir: &std::path::Path) -> Result<()> {
+        Err(Error::Unsupported(format!("{} does not support checkpoints", self.db_type())))
+    }
+    /// Clears reader slots left by dead processes, returning how many were cleared.
+    fn reader_check(&self) -> Result<usize> {
+        Err(Error::Unsupported(format!("{} has no reader table", self.db_type())))
+    }
+
+    fn reverse_encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>> {
+        self.key_codec().reverse_encode(entity, feature, ts)
+    }
+    fn encode(&self, entity: &str, feature: &str, ts: u64) -> Result<Vec<u8>> {
+        self.key_codec().encode(entity, feature, ts)
     }
 }
@@ -1,9 +1,107 @@
-use lmdb::{Database, DatabaseFlags, Environment, EnvironmentFlags, Transaction, WriteFlags};
-use crate::DbInterface;
+use lmdb::{Cursor, Database, DatabaseFlags, Environment, EnvironmentFlags, Transaction, WriteFlags};
+use lmdb_sys::{MDB_CP_COMPACT, MDB_FIRST, MDB_LAST, MDB_NEXT, MDB_PREV, MDB_SET_RANGE};
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Mutex;
+use crate::config::BackendConfig;
+use crate::catalog;
+use crate::{DbInterface, EntryVisitor, Error, KeyCodec, RawVector, Result, TimeOrder, ValueCodec};
 
 pub struct LmdbWrapper {
     env: Environment,
-    db: Database
+    db: Database,
+    key_codec: Box<dyn KeyCodec>,
+    value_codec: Box<dyn ValueCodec>,
+    read_only: bool,
+    /// Held while catalog entries are computed and written with the data.
+    catalog_lock: Mutex<()>,
+}
+
+impl LmdbWrapper {
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly("lmdb environment was opened with READ_ONLY".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Walks keys >= `start` in order inside one read transaction until `visit` returns false.
+    fn walk_from(&self, start: &[u8], mut visit: impl FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<()> {
+        let txn = self.env.begin_ro_txn()?;
+        let cursor = txn.open_ro_cursor(self.db)?;
+        // MDB_SET_RANGE positions at the first key >= start, MDB_NEXT walks forward;
+        // an empty key is rejected with MDB_BAD_VALSIZE, so start from the first key instead
+        let mut item = if start.is_empty() {
+            cursor.get(None, None, MDB_FIRST)
+        } else {
+            cursor.get(Some(start), None, MDB_SET_RANGE)
+        };
+        loop {
+            let (key, value) = match item {
+                Ok((key, value)) => (key.unwrap_or(start), value),
+                Err(lmdb::Error::NotFound) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            if !visit(key, value)? {
+                return Ok(());
+            }
+            item = cursor.get(None, None, MDB_NEXT);
+        }
+    }
+
+    /// Walks keys <= `end` in reverse order inside one read transaction until `visit` returns false.
+    fn walk_back_from(&self, end: &[u8], mut visit: impl FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<()> {
+        let txn = self.env.begin_ro_txn()?;
+        let cursor = txn.open_ro_cursor(self.db)?;
+        // there is no "last key <= end" op: land on the first key >= end and step back
+        // past it if it is greater, or start from the last key if everything is smaller
+        let mut item = match cursor.get(Some(end), None, MDB_SET_RANGE) {
+            Ok((Some(key), _)) if key > end => cursor.get(None, None, MDB_PREV),
+            Err(lmdb::Error::NotFound) => cursor.get(None, None, MDB_LAST),
+            item => item,
+        };
+        loop {
+            let (key, value) = match item {
+                Ok((key, value)) => (key.unwrap_or(end), value),
+                Err(lmdb::Error::NotFound) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            if !visit(key, value)? {
+                return Ok(());
+            }
+            item = cursor.get(None, None, MDB_PREV);
+        }
+    }
+}
+
+/// Turns the return code of a raw lmdb_sys call into a `Result`.
+fn ffi_result(code: c_int) -> Result<()> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(lmdb::Error::from_err_code(code).into())
+    }
+}
+
+impl From<lmdb::Error> for Error {
+    fn from(e: lmdb::Error) -> Self {
+        let message = e.to_string();
+        match e {
+            lmdb::Error::NotFound => Error::NotFound(message),
+            lmdb::Error::Corrupted
+            | lmdb::Error::PageNotFound
+            | lmdb::Error::Panic
+            | lmdb::Error::VersionMismatch
+            | lmdb::Error::Invalid => Error::Corruption(message),
+            lmdb::Error::MapFull | lmdb::Error::MapResized => Error::MapFull(message),
+            lmdb::Error::BadValSize => Error::InvalidKey(message),
+            // positive codes are plain errno values from the OS
+            lmdb::Error::Other(code) if code > 0 => Error::IoError(message),
+            _ => Error::BackendSpecific { backend: "lmdb", message },
+        }
+    }
 }
 
 impl DbInterface for LmdbWrapper {
@@ -11,48 +109,200 @@ impl DbInterface for LmdbWrapper {
         "lmdb".to_string()
     }
 
-    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        let mut txn = self.env.begin_rw_txn()?;
-        txn.put(self.db, &key, &value, WriteFlags::default())?;
-        txn.commit()?;
-        Ok(())
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.batch_put(&[(key.to_vec(), value.to_vec())])
     }
 
-    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let txn = self.env.begin_ro_txn()?;
         match txn.get(self.db, &key) {
             Ok(value) => Ok(Some(value.to_vec())),
             Err(lmdb::Error::NotFound) => Ok(None),
-            Err(e) => Err(Box::new(e)),
+            Err(e) => Err(e.into()),
         }
     }
 
-    fn batch_put(&self, items: &[(Vec<u8>, Vec<u8>)]) -> Result<(), Box<dyn std::error::Error>> {
+    fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let txn = self.env.begin_ro_txn()?;
+        keys.iter()
+            .map(|key| match txn.get(self.db, key) {
+                Ok(value) => Ok(Some(value.to_vec())),
+                Err(lmdb::Error::NotFound) => Ok(None),
+                Err(e) => Err(e.into()),
+            })
+            .collect()
+    }
+
+    fn keys_exist(&self, keys: &[&[u8]]) -> Result<Vec<bool>> {
+        let txn = self.env.begin_ro_txn()?;
+        keys.iter()
+            .map(|key| match txn.get(self.db, key) {
+                Ok(_) => Ok(true),
+                Err(lmdb::Error::NotFound) => Ok(false),
+                Err(e) => Err(e.into()),
+            })
+            .collect()
+    }
+
+    fn batch_put(&self, items: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        self.check_writable()?;
+        let _guard = self.catalog_lock.lock().unwrap();
+        // computed before the write transaction, since a thread cannot hold both kinds
+        let updates = catalog::catalog_updates(self, items)?;
         let mut txn = self.env.begin_rw_txn()?;
-        for (key, value) in items {
+        for (key, value) in items.iter().chain(&updates) {
             txn.put(self.db, key, value, WriteFlags::default())?;
         }
         txn.commit()?;
         Ok(())
     }
 
-    fn close(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let stat = self.env.stat().unwrap();
+    fn scan(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut items = Vec::new();
+        self.walk_from(start, |key, value| {
+            if items.len() >= limit {
+                return Ok(false);
+            }
+            items.push((key.to_vec(), value.to_vec()));
+            Ok(true)
+        })?;
+        Ok(items)
+    }
+
+    fn for_each_entry(&self, visit: &mut EntryVisitor<'_>) -> Result<()> {
+        self.walk_from(&[], visit)
+    }
+
+    fn close(&self) -> Result<()> {
+        // make sure every committed transaction is on disk before the process exits
+        if !self.read_only {
+            self.env.sync(true)?;
+        }
+        let stat = self.env.stat()?;
         println!("Closing LMDB environment, stats: {}, {}, {}", stat.page_size(), stat.depth(), stat.leaf_pages());
         Ok(())
     }
     
-    fn prefix_seek(&self, prefix: &str, start_ts: u16, end_ts: u16) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-        todo!()
+    fn prefix_seek_timed(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64) -> Result<Vec<(u64, RawVector)>> {
+        let (low_key, high_key) = self.key_codec.key_range(entity, feature, start_ts, end_ts)?;
+        let mut values = Vec::new();
+        self.walk_from(&low_key, |key, value| {
+            if key > high_key.as_slice() {
+                return Ok(false);
+            }
+            values.push((self.key_codec.decode_ts(key)?, self.value_codec.decode_raw(value)?));
+            Ok(true)
+        })?;
+        if self.key_codec.time_order(feature) == TimeOrder::Descending {
+            values.reverse();
+        }
+        Ok(values)
+    }
+
+    fn prefix_seek_last(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64, n: usize) -> Result<Vec<(u64, RawVector)>> {
+        let (low_key, high_key) = self.key_codec.key_range(entity, feature, start_ts, end_ts)?;
+        let mut values = Vec::new();
+        let mut visit = |key: &[u8], value: &[u8]| {
+            if values.len() >= n || key < low_key.as_slice() || key > high_key.as_slice() {
+                return Ok(false);
+            }
+            values.push((self.key_codec.decode_ts(key)?, self.value_codec.decode_raw(value)?));
+            Ok(true)
+        };
+        // newest keys are the highest in an ascending layout and the lowest in a descending one
+        match self.key_codec.time_order(feature) {
+            TimeOrder::Ascending => self.walk_back_from(&high_key, &mut visit)?,
+            TimeOrder::Descending => self.walk_from(&low_key, &mut visit)?,
+        }
+        values.reverse();
+        Ok(values)
+    }
+
+    fn key_codec(&self) -> &dyn KeyCodec {
+        self.key_codec.as_ref()
+    }
+
+    fn value_codec(&self) -> &dyn ValueCodec {
+        self.value_codec.as_ref()
+    }
+
+    fn stats(&self) -> Result<Vec<(String, String)>> {
+        let stat = self.env.stat()?;
+        let mut info = std::mem::MaybeUninit::<lmdb_sys::MDB_envinfo>::uninit();
+        // SAFETY: the environment is open for the lifetime of self and mdb_env_info fills `info`
+        let info = unsafe {
+            ffi_result(lmdb_sys::mdb_env_info(self.env.env(), info.as_mut_ptr()))?;
+            info.assume_init()
+        };
+        Ok(vec![
+            ("read_only".to_string(), self.read_only.to_string()),
+            ("entries".to_string(), stat.entries().to_string()),
+            ("depth".to_string(), stat.depth().to_string()),
+            ("page_size".to_string(), stat.page_size().to_string()),
+            ("branch_pages".to_string(), stat.branch_pages().to_string()),
+            ("leaf_pages".to_string(), stat.leaf_pages().to_string()),
+            ("overflow_pages".to_string(), stat.overflow_pages().to_string()),
+            ("map_size".to_string(), info.me_mapsize.to_string()),
+            ("last_pgno".to_string(), info.me_last_pgno.to_string()),
+            ("last_txnid".to_string(), info.me_last_txnid.to_string()),
+            ("max_readers".to_string(), info.me_maxreaders.to_string()),
+            ("num_readers".to_string(), info.me_numreaders.to_string()),
+        ])
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.check_writable()?;
+        self.env.sync(true)?;
+        Ok(())
+    }
+
+    fn checkpoint(&self, dir: &Path) -> Result<()> {
+        // mdb_env_copy2 needs an existing, empty directory
+        std::fs::create_dir(dir)?;
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| Error::InvalidConfig(format!("checkpoint path: {}", e)))?;
+        // SAFETY: the environment is open for the lifetime of self and `path` is NUL-terminated
+        ffi_result(unsafe { lmdb_sys::mdb_env_copy2(self.env.env(), path.as_ptr(), MDB_CP_COMPACT) })
+    }
+
+    fn reader_check(&self) -> Result<usize> {
+        let mut dead: c_int = 0;
+        // SAFETY: the environment is open for the lifetime of self
+        ffi_result(unsafe { lmdb_sys::mdb_reader_check(self.env.env(), &mut dead) })?;
+        Ok(dead as usize)
     }
 }
 
+pub fn open_lmdb(config: &BackendConfig) -> Result<Box<dyn DbInterface>> {
+    let mut builder = Environment::new();
+    builder
+        .set_map_size(config.lmdb.map_size)
+        .set_max_dbs(config.lmdb.max_dbs);
+    if config.is_read_only() {
+        builder.set_flags(EnvironmentFlags::READ_ONLY);
+    } else if config.create_if_missing {
+        std::fs::create_dir_all(&config.path)?;
+    }
+    let env = builder.open(&config.path)?;
+    let db = if config.is_read_only() {
+        env.open_db(None)?
+    } else {
+        env.create_db(None, DatabaseFlags::default())?
+    };
+    Ok(Box::new(LmdbWrapper {
+        env,
+        db,
+        key_codec: config.key_codec(),
+        value_codec: Box::new(config.value_codec()?),
+        read_only: config.is_read_only(),
+        catalog_lock: Mutex::new(()),
+    }))
+}
+
 pub fn setup_lmdb() -> Box<dyn DbInterface> {
-    let env = Environment::new()
-        .set_map_size(80*1024_usize.pow(3)) // 1TB
-        .set_max_dbs(1)
-        .open(std::path::Path::new("./lmdb_bench"))
-        .unwrap();
-    let db = env.create_db(None, DatabaseFlags::default()).unwrap();
-    Box::new(LmdbWrapper { env, db })
-} 
\ No newline at end of file
+    open_lmdb(&BackendConfig::new("./lmdb_bench")).unwrap()
+}
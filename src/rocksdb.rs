@@ -1,51 +1,286 @@
-use rocksdb::{Direction, IteratorMode, KeyEncodingType, Options, PlainTableFactoryOptions, SliceTransform, DB};
-use crate::DbInterface;
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{BlockBasedOptions, Cache, Direction, ErrorKind, IteratorMode, KeyEncodingType, Options, PlainTableFactoryOptions, ReadOptions, SliceTransform, DB};
+use crate::config::{BackendConfig, TableFormat};
+use crate::catalog;
+use crate::{DbInterface, EntryVisitor, Error, KeyCodec, RawVector, Result, TimeOrder, ValueCodec};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct RocksDbWrapper(DB);
+pub struct RocksDbWrapper {
+    db: Arc<DB>,
+    key_codec: Box<dyn KeyCodec>,
+    value_codec: Box<dyn ValueCodec>,
+    read_only: bool,
+    /// Set when opened as a secondary instance that follows a primary.
+    follower: Option<Arc<FollowerState>>,
+    /// PlainTable files only support forward iteration.
+    plain_table: bool,
+    /// Held while catalog entries are computed and written with the data.
+    catalog_lock: Mutex<()>,
+}
+
+struct FollowerState {
+    last_catch_up: Mutex<Instant>,
+}
+
+impl RocksDbWrapper {
+    pub fn new(db: DB, config: &BackendConfig) -> Result<Self> {
+        Ok(Self {
+            db: Arc::new(db),
+            key_codec: config.key_codec(),
+            value_codec: Box::new(config.value_codec()?),
+            read_only: config.is_read_only(),
+            follower: None,
+            plain_table: config.rocksdb.table_format == TableFormat::Plain,
+            catalog_lock: Mutex::new(()),
+        })
+    }
+
+    /// Calls `try_catch_up_with_primary` every `interval` until the wrapper is dropped.
+    fn start_catch_up(&mut self, interval: Duration) -> Result<()> {
+        let state = Arc::new(FollowerState { last_catch_up: Mutex::new(Instant::now()) });
+        let db = Arc::downgrade(&self.db);
+        let thread_state = state.clone();
+        thread::Builder::new()
+            .name("rocksdb-catch-up".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                let Some(db) = db.upgrade() else {
+                    break;
+                };
+                match db.try_catch_up_with_primary() {
+                    Ok(()) => *thread_state.last_catch_up.lock().unwrap() = Instant::now(),
+                    Err(e) => tracing::warn!("failed to catch up with primary: {}", e),
+                }
+            })?;
+        self.follower = Some(state);
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly("rocksdb was opened read-only or as a secondary".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl From<rocksdb::Error> for Error {
+    fn from(e: rocksdb::Error) -> Self {
+        let message = e.to_string();
+        match e.kind() {
+            ErrorKind::NotFound => Error::NotFound(message),
+            ErrorKind::Corruption => Error::Corruption(message),
+            ErrorKind::IOError
+            | ErrorKind::Busy
+            | ErrorKind::TimedOut
+            | ErrorKind::TryAgain
+            | ErrorKind::Incomplete
+            | ErrorKind::ShutdownInProgress => Error::IoError(message),
+            _ => Error::BackendSpecific { backend: "rocksdb", message },
+        }
+    }
+}
 
 impl DbInterface for RocksDbWrapper {
     fn db_type(&self) -> String {
         "rocksdb".to_string()
     }
 
-    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        self.0.put(key, value)?;
-        Ok(())
+    fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
-        Ok(self.0.get(key)?)
+    fn replication_lag(&self) -> Option<Duration> {
+        self.follower
+            .as_ref()
+            .map(|f| f.last_catch_up.lock().unwrap().elapsed())
     }
 
-    fn batch_put(&self, items: &[(Vec<u8>, Vec<u8>)]) -> Result<(), Box<dyn std::error::Error>> {
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.batch_put(&[(key.to_vec(), value.to_vec())])
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.db.multi_get(keys)
+            .into_iter()
+            .map(|r| r.map_err(Error::from))
+            .collect()
+    }
+
+    fn keys_exist(&self, keys: &[&[u8]]) -> Result<Vec<bool>> {
+        // the bloom filter answers most misses; hits are pinned, not copied
+        keys.iter()
+            .map(|key| {
+                if !self.db.key_may_exist(key) {
+                    return Ok(false);
+                }
+                Ok(self.db.get_pinned(key)?.is_some())
+            })
+            .collect()
+    }
+
+    fn batch_put(&self, items: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        self.check_writable()?;
+        let _guard = self.catalog_lock.lock().unwrap();
+        let updates = catalog::catalog_updates(self, items)?;
         let mut batch = rocksdb::WriteBatch::default();
-        for (key, value) in items {
+        for (key, value) in items.iter().chain(&updates) {
             batch.put(key, value);
         }
-        self.0.write(batch)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn scan(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db.iterator(IteratorMode::From(start, Direction::Forward))
+            .take(limit)
+            .map(|item| {
+                let (key, value) = item?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    fn for_each_entry(&self, visit: &mut EntryVisitor<'_>) -> Result<()> {
+        // with a prefix extractor, iteration past one prefix is only defined in total
+        // order; plain tables support that from the first key, but not for seeks
+        let mut opts = ReadOptions::default();
+        opts.set_total_order_seek(true);
+        for item in self.db.iterator_opt(IteratorMode::Start, opts) {
+            let (key, value) = item?;
+            if !visit(&key, &value)? {
+                break;
+            }
+        }
         Ok(())
     }
 
-    fn close(&self) -> Result<(), Box<dyn std::error::Error>> {
+    fn close(&self) -> Result<()> {
+        // the WAL already covers unflushed memtables; flushing just saves replaying it on reopen
+        if !self.read_only && self.follower.is_none() {
+            self.db.flush()?;
+        }
         Ok(())
     }
 
-    fn prefix_seek(&self, prefix: &str, start_ts: u16, end_ts: u16) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    fn prefix_seek_timed(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64) -> Result<Vec<(u64, RawVector)>> {
+        let (low_key, high_key) = self.key_codec.key_range(entity, feature, start_ts, end_ts)?;
         let mut values = Vec::new();
-        let iter = self.0.iterator(
-            IteratorMode::From(self.encode(prefix, start_ts).as_bytes(), Direction::Forward));
+        let iter = self.db.iterator(IteratorMode::From(&low_key, Direction::Forward));
         for item in iter {
             let (key, value) = item?;
-            if &*key > self.encode(prefix, end_ts).as_bytes() {
+            if *key > *high_key {
+                break;
+            }
+            values.push((self.key_codec.decode_ts(&key)?, self.value_codec.decode_raw(&value)?));
+        }
+        if self.key_codec.time_order(feature) == TimeOrder::Descending {
+            values.reverse();
+        }
+        Ok(values)
+    }
+
+    fn prefix_seek_last(&self, entity: &str, feature: &str, start_ts: u64, end_ts: u64, n: usize) -> Result<Vec<(u64, RawVector)>> {
+        let (low_key, high_key) = self.key_codec.key_range(entity, feature, start_ts, end_ts)?;
+        let ascending = self.key_codec.time_order(feature) == TimeOrder::Ascending;
+        if ascending && self.plain_table {
+            if n == 0 {
+                return Ok(Vec::new());
+            }
+            // no reverse seeks on plain tables: scan the range, keeping the last n
+            let mut values = VecDeque::with_capacity(n);
+            for item in self.db.iterator(IteratorMode::From(&low_key, Direction::Forward)) {
+                let (key, value) = item?;
+                if *key > *high_key {
+                    break;
+                }
+                if values.len() == n {
+                    values.pop_front();
+                }
+                values.push_back((key, value));
+            }
+            return values
+                .into_iter()
+                .map(|(key, value)| Ok((self.key_codec.decode_ts(&key)?, self.value_codec.decode_raw(&value)?)))
+                .collect();
+        }
+        let mut values = Vec::new();
+        // newest keys are the highest in an ascending layout and the lowest in a
+        // descending one; a reverse iterator starts at the last key <= high_key
+        let iter = if ascending {
+            self.db.iterator(IteratorMode::From(&high_key, Direction::Reverse))
+        } else {
+            self.db.iterator(IteratorMode::From(&low_key, Direction::Forward))
+        };
+        for item in iter.take(n) {
+            let (key, value) = item?;
+            if *key < *low_key || *key > *high_key {
                 break;
             }
-            values.extend_from_slice(&self.numpy_f32_vec(&value));
+            values.push((self.key_codec.decode_ts(&key)?, self.value_codec.decode_raw(&value)?));
         }
+        values.reverse();
         Ok(values)
     }
 
+    fn key_codec(&self) -> &dyn KeyCodec {
+        self.key_codec.as_ref()
+    }
+
+    fn value_codec(&self) -> &dyn ValueCodec {
+        self.value_codec.as_ref()
+    }
+
+    fn stats(&self) -> Result<Vec<(String, String)>> {
+        let mut stats = vec![("read_only".to_string(), self.read_only.to_string())];
+        for name in STATS_PROPERTIES {
+            if let Some(value) = self.db.property_int_value(name)? {
+                stats.push((name.to_string(), value.to_string()));
+            }
+        }
+        if let Some(lag) = self.replication_lag() {
+            stats.push(("replication_lag_ms".to_string(), lag.as_millis().to_string()));
+        }
+        Ok(stats)
+    }
+
+    fn compact_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<()> {
+        self.check_writable()?;
+        self.db.compact_range(start, end);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.check_writable()?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn checkpoint(&self, dir: &Path) -> Result<()> {
+        Checkpoint::new(&self.db)?.create_checkpoint(dir)?;
+        Ok(())
+    }
 }
 
+/// Integer properties reported by `stats`.
+const STATS_PROPERTIES: [&str; 7] = [
+    "rocksdb.estimate-num-keys",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.live-sst-files-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.estimate-pending-compaction-bytes",
+    "rocksdb.num-running-compactions",
+    "rocksdb.num-running-flushes",
+];
+
 // Add this struct and implementation before the open_rocks_readonly function
 /* 
 struct CustomPrefixTransform;
@@ -66,31 +301,86 @@ impl SliceTransform for CustomPrefixTransform {
 }
 */
 
-pub fn open_rocks_readonly() -> Box<dyn DbInterface> {
+/// Builds RocksDB options from the `[rocksdb]` section of the config.
+fn rocks_options(config: &BackendConfig) -> Options {
+    let rc = &config.rocksdb;
     let mut opts = Options::default();
-    //minimize background jobs since we are only reading
-    opts.set_max_background_jobs(0);
-    opts.set_max_write_buffer_number(0);
-    let factory_opts = PlainTableFactoryOptions {
-        user_key_length: 0,
-        bloom_bits_per_key: 20,
-        hash_table_ratio: 0.75,
-        index_sparseness: 16,
-        huge_page_tlb_size: 0,
-        encoding_type: KeyEncodingType::Plain,
-        full_scan_mode: false,
-        store_index_in_file: false,
-    };
-    opts.set_plain_table_factory(&factory_opts);
-    opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(10));
-    Box::new(RocksDbWrapper(DB::open(&opts, "./test.db").unwrap()))
+    opts.create_if_missing(config.create_if_missing && !config.is_read_only());
+    opts.set_write_buffer_size(rc.write_buffer_size);
+    opts.set_max_write_buffer_number(rc.max_write_buffer_number);
+    opts.set_max_background_jobs(rc.max_background_jobs);
+    match rc.table_format {
+        TableFormat::Plain => {
+            let factory_opts = PlainTableFactoryOptions {
+                user_key_length: 0,
+                bloom_bits_per_key: rc.bloom_bits_per_key,
+                hash_table_ratio: 0.75,
+                index_sparseness: 16,
+                huge_page_tlb_size: 0,
+                encoding_type: KeyEncodingType::Plain,
+                full_scan_mode: false,
+                store_index_in_file: false,
+            };
+            opts.set_plain_table_factory(&factory_opts);
+        }
+        TableFormat::BlockBased => {
+            let mut block_opts = BlockBasedOptions::default();
+            if rc.block_cache_size > 0 {
+                block_opts.set_block_cache(&Cache::new_lru_cache(rc.block_cache_size));
+            }
+            block_opts.set_bloom_filter(rc.bloom_bits_per_key as f64, false);
+            opts.set_block_based_table_factory(&block_opts);
+        }
+    }
+    if let Some(prefix_len) = rc.prefix_len {
+        opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(prefix_len));
+    }
+    if rc.row_cache_size > 0 {
+        opts.set_row_cache(&Cache::new_lru_cache(rc.row_cache_size));
+    }
+    opts
+}
+
+pub fn open_rocks(config: &BackendConfig) -> Result<Box<dyn DbInterface>> {
+    if config.rocksdb.secondary_path.is_some() {
+        return open_rocks_secondary(config);
+    }
+    if config.is_read_only() {
+        return open_rocks_readonly(config);
+    }
+    let db = DB::open(&rocks_options(config), &config.path)?;
+    Ok(Box::new(RocksDbWrapper::new(db, config)?))
+}
+
+/// Opens without taking the DB lock, so several processes can serve the same
+/// directory, including from a read-only mount. Writes fail with `Error::ReadOnly`.
+pub fn open_rocks_readonly(config: &BackendConfig) -> Result<Box<dyn DbInterface>> {
+    let db = DB::open_for_read_only(&rocks_options(config), &config.path, false)?;
+    let mut wrapper = RocksDbWrapper::new(db, config)?;
+    wrapper.read_only = true;
+    Ok(Box::new(wrapper))
+}
+
+/// Opens `config.path` as a secondary instance that tails the primary's
+/// MANIFEST and WAL, catching up every `rocksdb.catch_up_interval_ms`. One
+/// writer process can then ingest while several servers read fresh data.
+pub fn open_rocks_secondary(config: &BackendConfig) -> Result<Box<dyn DbInterface>> {
+    let secondary_path = config.rocksdb.secondary_path.as_ref().ok_or_else(|| {
+        Error::InvalidConfig("rocksdb.secondary_path is required for a secondary instance".to_string())
+    })?;
+    let mut opts = rocks_options(config);
+    // secondaries must keep every table file open to follow the primary
+    opts.set_max_open_files(-1);
+    let db = DB::open_as_secondary(&opts, config.path.as_path(), secondary_path.as_path())?;
+    let mut wrapper = RocksDbWrapper::new(db, config)?;
+    wrapper.read_only = true;
+    wrapper.start_catch_up(Duration::from_millis(config.rocksdb.catch_up_interval_ms))?;
+    Ok(Box::new(wrapper))
 }
 
 pub fn setup_rocks() -> Box<dyn DbInterface> {
-    let mut opts = Options::default();
-    opts.create_if_missing(true);
-    opts.set_write_buffer_size(1024 * 1024 * 1024); // 64MB
-    opts.set_max_write_buffer_number(3);
-    
-    Box::new(RocksDbWrapper(DB::open(&opts, "./rocksdb_bench").unwrap()))
-} 
\ No newline at end of file
+    let mut config = BackendConfig::new("./rocksdb_bench");
+    config.rocksdb.write_buffer_size = 1024 * 1024 * 1024;
+    config.rocksdb.max_write_buffer_number = 3;
+    open_rocks(&config).unwrap()
+}
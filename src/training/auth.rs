@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rand::RngCore;
+use serde::Deserialize;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Status};
+
+use blackhole::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    Write,
+    Admin,
+}
+
+/// Local credentials file, e.g.
+///
+/// ```toml
+/// session_ttl_secs = 3600
+///
+/// [[users]]
+/// name = "trainer"
+/// password = "..."
+/// permissions = ["read"]
+/// # optional prefix ACLs; an omitted list allows everything
+/// read = { features = ["user.*"], entities = ["us-"] }
+///
+/// [[users]]
+/// name = "ingest"
+/// token = "..."            # static bearer token, usable without a password
+/// permissions = ["read", "write"]
+/// ```
+///
+/// Secrets are stored as given, so keep the file readable by the server only.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    #[serde(default = "default_session_ttl_secs")]
+    pub session_ttl_secs: u64,
+    #[serde(default)]
+    pub users: Vec<User>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct User {
+    pub name: String,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    pub permissions: Vec<Permission>,
+    #[serde(default)]
+    pub read: Scope,
+    #[serde(default)]
+    pub write: Scope,
+}
+
+/// Feature and entity name prefixes a user may touch. A trailing `*` is
+/// accepted and ignored, so `user.*` and `user.` mean the same.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scope {
+    pub features: Option<Vec<String>>,
+    pub entities: Option<Vec<String>>,
+}
+
+impl Scope {
+    fn matches(prefixes: &Option<Vec<String>>, name: &str) -> bool {
+        match prefixes {
+            Some(prefixes) => prefixes.iter().any(|p| name.starts_with(p.trim_end_matches('*'))),
+            None => true,
+        }
+    }
+
+    /// Names in `features` and `entities` that fall outside this scope.
+    fn denied<'a>(&self, features: &[&'a str], entities: &[&'a str]) -> (Vec<&'a str>, Vec<&'a str>) {
+        (
+            features.iter().copied().filter(|f| !Self::matches(&self.features, f)).collect(),
+            entities.iter().copied().filter(|e| !Self::matches(&self.entities, e)).collect(),
+        )
+    }
+}
+
+fn default_session_ttl_secs() -> u64 {
+    3600
+}
+
+impl Credentials {
+    pub fn from_toml_file(path: impl AsRef<Path>) -> blackhole::Result<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("cannot read {}: {}", path.display(), e)))?;
+        let credentials: Self = toml::from_str(&s).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        if credentials.session_ttl_secs == 0 {
+            return Err(Error::InvalidConfig("session_ttl_secs must be positive".to_string()));
+        }
+        for user in &credentials.users {
+            if user.password.is_none() && user.token.is_none() {
+                return Err(Error::InvalidConfig(format!("user {:?} has neither a password nor a token", user.name)));
+            }
+        }
+        Ok(credentials)
+    }
+}
+
+/// The authenticated caller, attached to requests by the interceptor.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub principal: String,
+    pub permissions: Vec<Permission>,
+    read: Scope,
+    write: Scope,
+    expires: Instant,
+}
+
+impl Session {
+    /// The scope limiting `permission`; admin calls are not scoped.
+    fn scope(&self, permission: Permission) -> Option<&Scope> {
+        match permission {
+            Permission::Read => Some(&self.read),
+            Permission::Write => Some(&self.write),
+            Permission::Admin => None,
+        }
+    }
+
+    /// Whether `features` and `entities` all fall inside the caller's read or write scope.
+    pub fn allows(&self, permission: Permission, features: &[&str], entities: &[&str]) -> bool {
+        self.scope(permission).is_none_or(|scope| {
+            let (features, entities) = scope.denied(features, entities);
+            features.is_empty() && entities.is_empty()
+        })
+    }
+
+    /// Fails with `PermissionDenied` naming every feature or entity outside
+    /// the caller's read or write scope.
+    pub fn check_scope(&self, permission: Permission, features: &[&str], entities: &[&str]) -> Result<(), Status> {
+        let Some(scope) = self.scope(permission) else {
+            return Ok(());
+        };
+        let (features, entities) = scope.denied(features, entities);
+        if features.is_empty() && entities.is_empty() {
+            return Ok(());
+        }
+        let mut denied = Vec::new();
+        if !features.is_empty() {
+            denied.push(format!("features {:?}", features));
+        }
+        if !entities.is_empty() {
+            denied.push(format!("entities {:?}", entities));
+        }
+        Err(Status::permission_denied(format!(
+            "{} may not {:?} {}", self.principal, permission, denied.join(" and ")
+        )))
+    }
+}
+
+/// Issues session tokens on handshake and resolves them on later calls.
+pub struct Authenticator {
+    credentials: Credentials,
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+/// Compares secrets without exiting early on the first differing byte.
+fn secret_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn authorization(metadata: &MetadataMap) -> Result<Option<&str>, Status> {
+    metadata
+        .get("authorization")
+        .map(|value| value.to_str().map_err(|_| Status::unauthenticated("authorization header is not ASCII")))
+        .transpose()
+}
+
+impl Authenticator {
+    pub fn new(credentials: Credentials) -> Arc<Self> {
+        Arc::new(Self { credentials, sessions: RwLock::new(HashMap::new()) })
+    }
+
+    fn static_token_user(&self, token: &str) -> Option<&User> {
+        self.credentials
+            .users
+            .iter()
+            .find(|user| user.token.as_deref().is_some_and(|t| secret_eq(t, token)))
+    }
+
+    fn session(&self, user: &User) -> Session {
+        Session {
+            principal: user.name.clone(),
+            permissions: user.permissions.clone(),
+            read: user.read.clone(),
+            write: user.write.clone(),
+            expires: Instant::now() + Duration::from_secs(self.credentials.session_ttl_secs),
+        }
+    }
+
+    /// Checks `Basic <user:password>` or a static `Bearer <token>` and opens a session.
+    /// Returns the new session token.
+    pub fn handshake(&self, metadata: &MetadataMap) -> Result<String, Status> {
+        let header = authorization(metadata)?
+            .ok_or_else(|| Status::unauthenticated("handshake needs an authorization header"))?;
+        let user = if let Some(encoded) = header.strip_prefix("Basic ") {
+            let decoded = STANDARD
+                .decode(encoded)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(|| Status::unauthenticated("malformed basic credentials"))?;
+            let (name, password) = decoded
+                .split_once(':')
+                .ok_or_else(|| Status::unauthenticated("malformed basic credentials"))?;
+            self.credentials.users.iter().find(|user| {
+                user.name == name && user.password.as_deref().is_some_and(|p| secret_eq(p, password))
+            })
+        } else if let Some(token) = header.strip_prefix("Bearer ") {
+            self.static_token_user(token)
+        } else {
+            return Err(Status::unauthenticated("authorization must be Basic or Bearer"));
+        };
+        let user = user.ok_or_else(|| Status::unauthenticated("invalid credentials"))?;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let session = self.session(user);
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, s| s.expires > Instant::now());
+        sessions.insert(token.clone(), session);
+        Ok(token)
+    }
+
+    /// Interceptor body: resolves a `Bearer` session or static token into a
+    /// `Session` extension. Requests without one pass through unauthenticated,
+    /// so the handshake itself can reach the server; each RPC decides what it needs.
+    pub fn intercept(&self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        // the handshake carries Basic credentials rather than a token
+        let Some(token) = authorization(request.metadata())?.and_then(|h| h.strip_prefix("Bearer ")) else {
+            return Ok(request);
+        };
+        let session = match self.sessions.read().unwrap().get(token) {
+            Some(session) if session.expires > Instant::now() => Some(session.clone()),
+            Some(_) => return Err(Status::unauthenticated("session token expired")),
+            None => self.static_token_user(token).map(|user| self.session(user)),
+        };
+        if let Some(session) = session {
+            request.extensions_mut().insert(session);
+        }
+        Ok(request)
+    }
+}
+
+/// Fails unless the interceptor attached a session holding `permission`.
+/// A server without an `Authenticator` lets every request through.
+pub fn require<T>(auth: Option<&Authenticator>, request: &Request<T>, permission: Permission) -> Result<(), Status> {
+    if auth.is_none() {
+        return Ok(());
+    }
+    let session = request
+        .extensions()
+        .get::<Session>()
+        .ok_or_else(|| Status::unauthenticated("call handshake first and send the session token as Bearer"))?;
+    if !session.permissions.contains(&permission) {
+        return Err(Status::permission_denied(format!("{} lacks {:?} permission", session.principal, permission)));
+    }
+    Ok(())
+}
+
+/// Response header carrying a freshly issued session token.
+pub fn bearer(token: &str) -> Result<MetadataValue<tonic::metadata::Ascii>, Status> {
+    format!("Bearer {}", token)
+        .parse()
+        .map_err(|_| Status::internal("session token is not a valid header value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn authenticator() -> Arc<Authenticator> {
+        let credentials: Credentials = toml::from_str(
+            r#"
+            [[users]]
+            name = "trainer"
+            password = "pw"
+            permissions = ["read"]
+            read = { features = ["user.*"], entities = ["us-"] }
+
+            [[users]]
+            name = "ingest"
+            token = "secret"
+            permissions = ["read", "write"]
+            "#,
+        )
+        .unwrap();
+        Authenticator::new(credentials)
+    }
+
+    fn bearer_request(token: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request.metadata_mut().insert("authorization", bearer(token).unwrap());
+        request
+    }
+
+    #[test]
+    fn scope_prefixes_ignore_trailing_star() {
+        let prefixes = Some(vec!["user.*".to_string(), "item".to_string()]);
+        assert!(Scope::matches(&prefixes, "user.age"));
+        assert!(Scope::matches(&prefixes, "items"));
+        assert!(!Scope::matches(&prefixes, "user"));
+        assert!(!Scope::matches(&prefixes, "other"));
+        assert!(Scope::matches(&None, "anything"));
+    }
+
+    #[test]
+    fn secrets_compare_exactly() {
+        assert!(secret_eq("secret", "secret"));
+        assert!(!secret_eq("secret", "secreT"));
+        assert!(!secret_eq("secret", "secret2"));
+        assert!(!secret_eq("", "s"));
+    }
+
+    #[test]
+    fn check_scope_names_denied_features_and_entities() {
+        let auth = authenticator();
+        let session = auth.session(&auth.credentials.users[0]);
+        session.check_scope(Permission::Read, &["user.age"], &["us-1"]).unwrap();
+        assert!(session.allows(Permission::Read, &["user.age"], &[]));
+        assert!(!session.allows(Permission::Read, &["item.price"], &[]));
+
+        let err = session.check_scope(Permission::Read, &["user.age", "item.price"], &["eu-1"]).unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+        assert!(err.message().contains("item.price") && err.message().contains("eu-1"));
+        assert!(!err.message().contains("user.age"));
+        // write and admin scopes are unrestricted for this user
+        session.check_scope(Permission::Write, &["item.price"], &["eu-1"]).unwrap();
+        session.check_scope(Permission::Admin, &["item.price"], &["eu-1"]).unwrap();
+    }
+
+    #[test]
+    fn intercept_resolves_static_and_session_tokens() {
+        let auth = authenticator();
+        let request = auth.intercept(bearer_request("secret")).unwrap();
+        assert_eq!(request.extensions().get::<Session>().unwrap().principal, "ingest");
+        require(Some(&auth), &request, Permission::Write).unwrap();
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert("authorization", format!("Basic {}", STANDARD.encode("trainer:pw")).parse().unwrap());
+        let token = auth.handshake(&metadata).unwrap();
+        let request = auth.intercept(bearer_request(&token)).unwrap();
+        require(Some(&auth), &request, Permission::Read).unwrap();
+        let err = require(Some(&auth), &request, Permission::Write).unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+    }
+
+    #[test]
+    fn unknown_bearer_tokens_get_no_session() {
+        let auth = authenticator();
+        let request = auth.intercept(bearer_request("nope")).unwrap();
+        assert!(request.extensions().get::<Session>().is_none());
+        let err = require(Some(&auth), &request, Permission::Read).unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+        // without an authenticator every request passes
+        require(None, &request, Permission::Admin).unwrap();
+    }
+
+    #[test]
+    fn expired_sessions_are_rejected() {
+        let auth = authenticator();
+        let mut session = auth.session(&auth.credentials.users[0]);
+        session.expires = Instant::now() - Duration::from_secs(1);
+        auth.sessions.write().unwrap().insert("stale".to_string(), session);
+        let err = auth.intercept(bearer_request("stale")).unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+    }
+}
@@ -0,0 +1,254 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use blackhole::{BackendConfig, DatabaseType, Error, Result, TimeBucketing};
+
+use crate::{DEFAULT_MAX_BATCH_BYTES, DEFAULT_MAX_BATCH_ROWS};
+
+/// Arrow Flight server for training reads and writes. Flags override the
+/// matching fields of the config file.
+#[derive(Debug, Parser)]
+#[command(name = "training")]
+pub struct Cli {
+    /// Server config file (TOML)
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Backend config file (TOML), used instead of the `[storage]` section
+    #[arg(long)]
+    pub storage_config: Option<PathBuf>,
+    /// Address to serve on, e.g. 0.0.0.0:50051
+    #[arg(long)]
+    pub listen: Option<SocketAddr>,
+    /// Address of the Prometheus `/metrics` endpoint
+    #[arg(long)]
+    pub metrics_listen: Option<SocketAddr>,
+    #[arg(long, value_enum)]
+    pub backend: Option<Backend>,
+    /// Data directory, overriding `storage.path`
+    #[arg(long)]
+    pub data_path: Option<PathBuf>,
+    /// Tokio worker threads; defaults to one per core
+    #[arg(long)]
+    pub worker_threads: Option<usize>,
+    /// Largest gRPC message accepted or sent, in bytes
+    #[arg(long)]
+    pub max_message_size: Option<usize>,
+    /// Log filter, e.g. `info` or `blackhole=debug,tonic=warn`
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Credentials file; without one every client is trusted
+    #[arg(long)]
+    pub credentials: Option<PathBuf>,
+    /// Seconds to let open streams finish after SIGINT/SIGTERM
+    #[arg(long)]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// Directory the `checkpoint` admin action writes under
+    #[arg(long)]
+    pub checkpoint_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Rocksdb,
+    Lmdb,
+}
+
+impl Backend {
+    pub fn database_type(self) -> DatabaseType {
+        match self {
+            Backend::Rocksdb => DatabaseType::RocksDB,
+            Backend::Lmdb => DatabaseType::LMDB,
+        }
+    }
+}
+
+/// Training server config, e.g.
+///
+/// ```toml
+/// listen = "0.0.0.0:50051"
+/// backend = "lmdb"
+/// log_level = "info"
+///
+/// [storage]
+/// path = "/data/features.lmdb"
+/// read_only = false
+/// ```
+///
+/// `[storage]` takes the fields of `BackendConfig`; see `backend.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    /// HTTP address serving Prometheus metrics on `/metrics`.
+    pub metrics_listen: SocketAddr,
+    pub backend: Backend,
+    /// Tokio worker threads; `None` uses one per core.
+    pub worker_threads: Option<usize>,
+    /// Largest gRPC message accepted or sent, in bytes.
+    pub max_message_size: usize,
+    /// `tracing` filter directive, e.g. `info` or `blackhole=debug,tonic=warn`.
+    pub log_level: String,
+    pub credentials: Option<PathBuf>,
+    /// Caps on the entities and estimated bytes of one `do_get` batch.
+    pub max_batch_rows: usize,
+    pub max_batch_bytes: usize,
+    /// Blocking lookup tasks run at once; `None` uses one per core.
+    pub lookup_parallelism: Option<usize>,
+    /// How long open streams may keep running after SIGINT/SIGTERM before the
+    /// backend is closed under them.
+    pub shutdown_timeout_secs: u64,
+    /// Root of the directories written by the `checkpoint` action; `None`
+    /// disables the action.
+    pub checkpoint_dir: Option<PathBuf>,
+    pub bucketing: BucketingConfig,
+    pub storage: BackendConfig,
+}
+
+/// How timestamp start/end columns in tickets and `do_put` map onto the time
+/// slots of the keys: slot `n` covers `width_ms` milliseconds from
+/// `origin_ms + n * width_ms`. Disabled, only integer slots are accepted.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BucketingConfig {
+    pub enabled: bool,
+    pub origin_ms: u64,
+    pub width_ms: u64,
+}
+
+impl Default for BucketingConfig {
+    fn default() -> Self {
+        // hourly slots from the Unix epoch
+        Self { enabled: true, origin_ms: 0, width_ms: 60 * 60 * 1000 }
+    }
+}
+
+impl BucketingConfig {
+    pub fn time_bucketing(&self) -> Option<TimeBucketing> {
+        self.enabled.then(|| TimeBucketing::new(self.origin_ms, self.width_ms))
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "[::1]:50051".parse().unwrap(),
+            metrics_listen: "[::1]:9090".parse().unwrap(),
+            backend: Backend::Rocksdb,
+            worker_threads: None,
+            max_message_size: 4 * 1024 * 1024,
+            log_level: "info".to_string(),
+            credentials: None,
+            max_batch_rows: DEFAULT_MAX_BATCH_ROWS,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            lookup_parallelism: None,
+            shutdown_timeout_secs: 30,
+            checkpoint_dir: None,
+            bucketing: BucketingConfig::default(),
+            storage: default_storage(),
+        }
+    }
+}
+
+/// ./test.db with block-based tables and no prefix extractor, which suit the
+/// variable-length binary keys.
+fn default_storage() -> BackendConfig {
+    let mut config = BackendConfig::new("./test.db");
+    config.rocksdb.bloom_bits_per_key = 20;
+    config
+}
+
+impl ServerConfig {
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("cannot read {}: {}", path.display(), e)))?;
+        toml::from_str(&s).map_err(|e| Error::InvalidConfig(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads the config file named on the command line, if any, applies the
+    /// flags on top and validates the result.
+    pub fn load(cli: Cli) -> Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_toml_file(path)?,
+            None => Self::default(),
+        };
+        if let Some(path) = &cli.storage_config {
+            config.storage = BackendConfig::from_toml_file(path)?;
+        }
+        if let Some(listen) = cli.listen {
+            config.listen = listen;
+        }
+        if let Some(listen) = cli.metrics_listen {
+            config.metrics_listen = listen;
+        }
+        if let Some(backend) = cli.backend {
+            config.backend = backend;
+        }
+        if let Some(path) = cli.data_path {
+            config.storage.path = path;
+        }
+        if let Some(threads) = cli.worker_threads {
+            config.worker_threads = Some(threads);
+        }
+        if let Some(size) = cli.max_message_size {
+            config.max_message_size = size;
+        }
+        if let Some(level) = cli.log_level {
+            config.log_level = level;
+        }
+        if let Some(path) = cli.credentials {
+            config.credentials = Some(path);
+        }
+        if let Some(secs) = cli.shutdown_timeout_secs {
+            config.shutdown_timeout_secs = secs;
+        }
+        if let Some(path) = cli.checkpoint_dir {
+            config.checkpoint_dir = Some(path);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.storage.validate().map_err(|e| match e {
+            Error::InvalidConfig(msg) => Error::InvalidConfig(format!("storage: {}", msg)),
+            e => e,
+        })?;
+        if self.worker_threads == Some(0) {
+            return Err(Error::InvalidConfig("worker_threads must be positive".to_string()));
+        }
+        if self.lookup_parallelism == Some(0) {
+            return Err(Error::InvalidConfig("lookup_parallelism must be positive".to_string()));
+        }
+        if self.max_batch_rows == 0 {
+            return Err(Error::InvalidConfig("max_batch_rows must be positive".to_string()));
+        }
+        if self.max_batch_bytes == 0 {
+            return Err(Error::InvalidConfig("max_batch_bytes must be positive".to_string()));
+        }
+        if self.max_message_size < self.max_batch_bytes {
+            return Err(Error::InvalidConfig(format!(
+                "max_message_size ({}) must be at least max_batch_bytes ({})",
+                self.max_message_size, self.max_batch_bytes
+            )));
+        }
+        if self.bucketing.enabled && self.bucketing.width_ms == 0 {
+            return Err(Error::InvalidConfig("bucketing.width_ms must be positive".to_string()));
+        }
+        if self.metrics_listen == self.listen {
+            return Err(Error::InvalidConfig(format!("metrics_listen must differ from listen ({})", self.listen)));
+        }
+        self.log_filter()?;
+        Ok(())
+    }
+
+    pub fn log_filter(&self) -> Result<EnvFilter> {
+        EnvFilter::try_new(&self.log_level)
+            .map_err(|e| Error::InvalidConfig(format!("log_level {:?}: {}", self.log_level, e)))
+    }
+}
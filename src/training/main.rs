@@ -1,28 +1,168 @@
+mod auth;
+mod config;
+mod metrics;
+
 use arrow_flight::{
-    encode::FlightDataEncoderBuilder, flight_service_server::{FlightService, FlightServiceServer}, Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo, HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket
+    decode::FlightRecordBatchStream, encode::FlightDataEncoderBuilder, error::FlightError, flight_descriptor::DescriptorType, flight_service_server::{FlightService, FlightServiceServer}, Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaAsIpc, SchemaResult, Ticket
 };
-use std::{ops::Deref, pin::Pin, sync::Arc};
+use std::{collections::{BTreeSet, HashMap, VecDeque}, ops::{Deref, Range}, pin::Pin, sync::Arc, time::{Duration, Instant}};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::task::JoinHandle;
+use tokio::sync::Semaphore;
 use tonic::{Request, Response, Status, Streaming};
 use futures::{stream::{self, BoxStream}, Stream};
 use futures::{StreamExt, TryStreamExt};
-use blackhole::{rocksdb, DatabaseType};
+use blackhole::{catalog, rocksdb, BackendConfig, DatabaseType, FeatureStats, RawVector, TimeBucketing};
 use blackhole::DbInterface;
+use blackhole::codec::{Endianness, VALUE_HEADER_LEN};
 use bytes::Bytes;
-use arrow::array::{Array, Float32Array, Int16Array, Int32Array, ListArray, RecordBatch, StringArray, StructArray, UInt8Array};
+use auth::{Authenticator, Credentials, Permission, Session};
+use clap::Parser;
+use config::{Cli, ServerConfig};
+use metrics::{Metered, Metrics};
+use tonic::service::interceptor::InterceptedService;
+use arrow::array::{Array, ArrayRef, AsArray, BinaryArray, BooleanArray, FixedSizeListArray, Float32Array, Int32Array, ListArray, RecordBatch, StringArray, StructArray, UInt64Array, UInt8Array};
+use arrow::buffer::{NullBuffer, OffsetBuffer};
 use arrow::ipc::reader::StreamReader;
-use arrow::datatypes::{DataType, Field, Schema};
+use arrow::ipc::writer::{IpcWriteOptions, StreamWriter};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Fields, Float32Type, SchemaRef, Int64Type, Schema, TimeUnit, TimestampMillisecondType, UInt64Type};
+
+
+/// Admin actions served by `do_action`, with the descriptions `list_actions` advertises.
+const ADMIN_ACTIONS: [(&str, &str); 5] = [
+    ("stats", "Backend counters as a JSON object"),
+    ("compact_range", "Compact keys in [start, end]; body is \"<start hex>:<end hex>\", either side may be empty"),
+    ("flush", "Persist buffered writes to disk"),
+    ("checkpoint", "Write a consistent copy of the store; body is a new directory, relative to the checkpoint root"),
+    ("reader_check", "Clear reader slots left by dead processes (LMDB)"),
+];
+
+/// The vectors one (id, feature) range returned, with their timestamps; empty if missing.
+type Vectors = Vec<(u64, RawVector)>;
+
+/// Default cap on entities per `do_get` batch.
+const DEFAULT_MAX_BATCH_ROWS: usize = 1024;
+/// Default cap on the estimated size of a `do_get` batch; matches the message
+/// size above which the Flight encoder starts splitting batches.
+const DEFAULT_MAX_BATCH_BYTES: usize = 2 * 1024 * 1024;
+
+/// Ids looked up by one blocking task in `do_get`.
+const LOOKUP_CHUNK_IDS: usize = 16;
 
+/// A decoded `do_get` ticket.
+pub struct TicketRequest {
+    ids: Vec<String>,
+    /// Read every entity of the requested features; `ids` is empty and is
+    /// filled in by `do_get`.
+    all_entities: bool,
+    /// (name, start, end) with inclusive time slots; a null start or end in
+    /// the ticket means the earliest or latest slot.
+    features: Vec<(String, u64, u64)>,
+    /// Keep only the newest `n` vectors of each range.
+    last_n: Option<usize>,
+    /// Return stored vectors as encoded bytes instead of dequantized f32.
+    raw: bool,
+    /// Concatenate each feature's vectors into one flat column, as older clients expect.
+    flat: bool,
+    /// Fail the whole request on the first missing (id, feature) instead of returning nulls.
+    strict: bool,
+    /// Add a `{feature}_missing` Boolean column per feature (nested layout only).
+    missing_mask: bool,
+}
 
 pub struct FlightDbServer {
-    db: Box<dyn DbInterface>,
+    db: Arc<dyn DbInterface>,
+    bucketing: Option<TimeBucketing>,
+    /// When set, `do_get`, `do_put` and `do_action` require a session from `handshake`.
+    auth: Option<Arc<Authenticator>>,
+    /// Caps on the entities and estimated bytes of one `do_get` batch.
+    max_batch_rows: usize,
+    max_batch_bytes: usize,
+    /// Bounds the blocking lookup tasks running at once, across all requests.
+    lookup_permits: Arc<Semaphore>,
+    metrics: Option<Arc<Metrics>>,
+    /// Directory under which the `checkpoint` action writes; unset, the action is refused.
+    checkpoint_root: Option<Arc<Path>>,
+    /// Set once shutdown begins; blocking backend work is refused from then on.
+    closing: Arc<AtomicBool>,
 }
 
 impl FlightDbServer {
-    pub fn new(db_type: DatabaseType) -> Self {
-        Self { db: db_type.create_db() }
+    pub fn new(db_type: DatabaseType, config: &BackendConfig) -> blackhole::Result<Self> {
+        Ok(Self {
+            db: Arc::from(db_type.create_db(config)?),
+            bucketing: None,
+            auth: None,
+            max_batch_rows: DEFAULT_MAX_BATCH_ROWS,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            lookup_permits: Arc::new(Semaphore::new(
+                std::thread::available_parallelism().map_or(4, |n| n.get()),
+            )),
+            metrics: None,
+            checkpoint_root: None,
+            closing: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn with_bucketing(mut self, bucketing: TimeBucketing) -> Self {
+        self.bucketing = Some(bucketing);
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Records per-feature reads and returned bytes of `do_get` into `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Lets the `checkpoint` action write new directories under `root`.
+    pub fn with_checkpoint_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.checkpoint_root = Some(Arc::from(root.into()));
+        self
+    }
+
+    /// Runs at most `tasks` blocking lookup tasks at once; defaults to the number of cores.
+    pub fn with_lookup_parallelism(mut self, tasks: usize) -> Self {
+        self.lookup_permits = Arc::new(Semaphore::new(tasks.max(1)));
+        self
+    }
+
+    /// Caps `do_get` batches at `max_rows` entities and roughly `max_bytes`;
+    /// an entity larger than `max_bytes` still gets a batch of its own.
+    pub fn with_batch_limits(mut self, max_rows: usize, max_bytes: usize) -> Self {
+        self.max_batch_rows = max_rows.max(1);
+        self.max_batch_bytes = max_bytes;
+        self
+    }
+
+    /// Setting the flag makes every later request that needs the backend fail with `Unavailable`.
+    pub fn closing(&self) -> Arc<AtomicBool> {
+        self.closing.clone()
+    }
+
+    fn require<T>(&self, request: &Request<T>, permission: Permission) -> Result<(), Status> {
+        auth::require(self.auth.as_deref(), request, permission)
+    }
+
+    /// Fails unless the caller may read `features` and `entities`. Returns the
+    /// caller's session, if the server checks credentials.
+    fn require_read<T>(&self, request: &Request<T>, features: &[&str], entities: &[&str]) -> Result<Option<Session>, Status> {
+        self.require(request, Permission::Read)?;
+        let session = request.extensions().get::<Session>().cloned();
+        if let Some(session) = &session {
+            session.check_scope(Permission::Read, features, entities)?;
+        }
+        Ok(session)
     }
 
-    fn decode_ticket(&self, ticket: &[u8]) -> Result<(Vec<String>, Vec<(String, Option<i16>, Option<i16>)>), Status> {
+    fn decode_ticket(&self, ticket: &[u8]) -> Result<TicketRequest, Status> {
         // Create a stream reader
         let mut reader = StreamReader::try_new(ticket, None)
             .map_err(|e| Status::internal(format!("Failed to create reader: {}", e)))?;
@@ -80,29 +220,554 @@ impl FlightDbServer {
 
         let starts = features_struct
             .column_by_name("start")
-            .ok_or_else(|| Status::internal("start field not found"))?
-            .as_any()
-            .downcast_ref::<Int16Array>()
-            .ok_or_else(|| Status::internal("Failed to downcast start to Int32Array"))?;
+            .ok_or_else(|| Status::internal("start field not found"))?;
+        let starts = Self::decode_timestamps(self.bucketing, starts, "start")?;
 
         let ends = features_struct
             .column_by_name("end")
-            .ok_or_else(|| Status::internal("end field not found"))?
-            .as_any()
-            .downcast_ref::<Int16Array>()
-            .ok_or_else(|| Status::internal("Failed to downcast end to Int32Array"))?;
-
-        let features: Vec<(String, Option<i16>, Option<i16>)> = (0..names.len())
-            .map(|i| {
-                (
-                    names.value(i).to_string(),
-                    if starts.is_null(i) { None } else { Some(starts.value(i)) },
-                    if ends.is_null(i) { None } else { Some(ends.value(i)) },
-                )
+            .ok_or_else(|| Status::internal("end field not found"))?;
+        let ends = Self::decode_timestamps(self.bucketing, ends, "end")?;
+
+        let features: Vec<(String, u64, u64)> = (0..names.len())
+            .map(|i| (names.value(i).to_string(), starts[i].unwrap_or(0), ends[i].unwrap_or(u64::MAX)))
+            .collect();
+
+        // Optional flags: quantized values as stored, and the pre-nesting flat layout
+        let raw = Self::ticket_flag(data_struct, "raw")?;
+        let flat = Self::ticket_flag(data_struct, "flat")?;
+        let strict = Self::ticket_flag(data_struct, "strict")?;
+        let missing_mask = Self::ticket_flag(data_struct, "missing_mask")?;
+        let all_entities = Self::ticket_flag(data_struct, "all_entities")?;
+        if all_entities && !ids.is_empty() {
+            return Err(Status::invalid_argument("a ticket with all_entities must not list ids"));
+        }
+
+        // Optional count of most recent vectors per range; absent or null means all
+        let last_n = match data_struct.column_by_name("last_n") {
+            Some(column) if !column.is_empty() => {
+                let values = cast(column, &DataType::Int64)
+                    .map_err(|e| Status::invalid_argument(format!("Failed to cast last_n to Int64: {}", e)))?;
+                match values.as_primitive::<Int64Type>().iter().next().flatten() {
+                    Some(n) if n <= 0 => return Err(Status::invalid_argument(format!("last_n must be positive, got {}", n))),
+                    n => n.map(|n| n as usize),
+                }
+            }
+            _ => None,
+        };
+
+        Ok(TicketRequest { ids, all_entities, features, last_n, raw, flat, strict, missing_mask })
+    }
+
+    /// Reads an optional boolean field of the ticket; absent or null means false.
+    fn ticket_flag(data_struct: &StructArray, name: &str) -> Result<bool, Status> {
+        match data_struct.column_by_name(name) {
+            Some(flag) => {
+                let flag = flag
+                    .as_any()
+                    .downcast_ref::<BooleanArray>()
+                    .ok_or_else(|| Status::internal(format!("Failed to downcast {} to BooleanArray", name)))?;
+                Ok(!flag.is_empty() && flag.is_valid(0) && flag.value(0))
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Reads a start/end column as time slots. Integer columns are taken as
+    /// slots directly; timestamp columns are mapped through the server's bucketing.
+    fn decode_timestamps(bucketing: Option<TimeBucketing>, column: &ArrayRef, name: &str) -> Result<Vec<Option<u64>>, Status> {
+        match column.data_type() {
+            DataType::UInt64 => Ok(column.as_primitive::<UInt64Type>().iter().collect()),
+            DataType::Timestamp(_, _) => {
+                let bucketing = bucketing.ok_or_else(|| {
+                    Status::invalid_argument(format!("{} is a timestamp but no time bucketing is configured", name))
+                })?;
+                let millis = cast(column, &DataType::Timestamp(TimeUnit::Millisecond, None))
+                    .map_err(|e| Status::invalid_argument(format!("Failed to cast {} to milliseconds: {}", name, e)))?;
+                Ok(millis
+                    .as_primitive::<TimestampMillisecondType>()
+                    .iter()
+                    .map(|ms| ms.map(|ms| bucketing.slot(ms)))
+                    .collect())
+            }
+            _ => {
+                let values = cast(column, &DataType::Int64)
+                    .map_err(|e| Status::invalid_argument(format!("Failed to cast {} to Int64: {}", name, e)))?;
+                values
+                    .as_primitive::<Int64Type>()
+                    .iter()
+                    .map(|v| match v {
+                        Some(v) if v < 0 => Err(Status::invalid_argument(format!("{} must not be negative, got {}", name, v))),
+                        v => Ok(v.map(|v| v as u64)),
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Turns one `do_put` batch with columns (id, feature, ts, embedding) into
+    /// encoded key/value pairs for `DbInterface::batch_put`.
+    fn encode_put_batch(db: &dyn DbInterface, bucketing: Option<TimeBucketing>, batch: &RecordBatch) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Status> {
+        let column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .ok_or_else(|| Status::invalid_argument(format!("{} column not found", name)))
+        };
+        let ids = column("id")?
+            .as_string_opt::<i32>()
+            .ok_or_else(|| Status::invalid_argument("Failed to downcast id to StringArray"))?;
+        let features = column("feature")?
+            .as_string_opt::<i32>()
+            .ok_or_else(|| Status::invalid_argument("Failed to downcast feature to StringArray"))?;
+        let timestamps = Self::decode_timestamps(bucketing, column("ts")?, "ts")?;
+        let embeddings = column("embedding")?
+            .as_fixed_size_list_opt()
+            .ok_or_else(|| Status::invalid_argument("embedding must be FixedSizeList<Float32>"))?;
+        let values = embeddings
+            .values()
+            .as_primitive_opt::<Float32Type>()
+            .ok_or_else(|| Status::invalid_argument("embedding must be FixedSizeList<Float32>"))?;
+        let dim = embeddings.value_length() as usize;
+
+        (0..batch.num_rows())
+            .map(|row| {
+                if ids.is_null(row) || features.is_null(row) || embeddings.is_null(row) {
+                    return Err(Status::invalid_argument(format!("row {} has a null id, feature or embedding", row)));
+                }
+                let ts = timestamps[row].ok_or_else(|| Status::invalid_argument(format!("row {} has a null ts", row)))?;
+                let key = db.encode(ids.value(row), features.value(row), ts)?;
+                let offset = embeddings.value_offset(row) as usize;
+                let value = db.value_codec().encode(&values.values()[offset..offset + dim])?;
+                Ok((key, value))
             })
+            .collect()
+    }
+
+    /// Rejects a `do_put` batch if any row falls outside the caller's write scope.
+    fn check_put_scope(session: &Session, batch: &RecordBatch) -> Result<(), Status> {
+        let names = |name: &str| -> Result<Vec<&str>, Status> {
+            let column = batch
+                .column_by_name(name)
+                .and_then(|c| c.as_string_opt::<i32>())
+                .ok_or_else(|| Status::invalid_argument(format!("{} must be a string column", name)))?;
+            let mut names: Vec<&str> = column.iter().flatten().collect();
+            names.sort_unstable();
+            names.dedup();
+            Ok(names)
+        };
+        session.check_scope(Permission::Write, &names("feature")?, &names("id")?)
+    }
+
+    /// Reads every (id, feature) range of the request, one row per id in
+    /// request order; an empty range marks a miss. Ids are split into chunks
+    /// that run on the blocking pool, at most `lookup_permits` at a time, so
+    /// large requests use several cores without stalling the async workers.
+    /// Also returns the catalog entry of each requested feature, read once on
+    /// the blocking pool as well.
+    async fn lookup(&self, request: &TicketRequest) -> Result<(Vec<Option<FeatureStats>>, Vec<Vec<Vectors>>), Status> {
+        let db = self.db.clone();
+        let names: Vec<String> = request.features.iter().map(|(name, _, _)| name.clone()).collect();
+        let stats = spawn_blocking(&self.closing, move || {
+            names
+                .iter()
+                .map(|name| catalog::feature_stats(db.as_ref(), name))
+                .collect::<blackhole::Result<Vec<_>>>()
+        })?
+        .await
+        .map_err(|e| Status::internal(format!("catalog lookup failed: {}", e)))??;
+        // one metrics label per requested feature, filled only when metrics are on
+        let labels: Arc<Vec<String>> = Arc::new(match &self.metrics {
+            Some(_) => request.features
+                .iter()
+                .zip(&stats)
+                .map(|((name, _, _), stats)| Metrics::feature_label(name, stats.as_ref()).to_string())
+                .collect(),
+            None => Vec::new(),
+        });
+        let mut tasks = Vec::new();
+        for ids in request.ids.chunks(LOOKUP_CHUNK_IDS) {
+            let permit = self.lookup_permits.clone().acquire_owned().await
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let db = self.db.clone();
+            let ids = ids.to_vec();
+            let features = request.features.clone();
+            let strict = request.strict;
+            let last_n = request.last_n;
+            let metrics = self.metrics.clone();
+            let labels = labels.clone();
+            tasks.push(spawn_blocking(&self.closing, move || {
+                let _permit = permit;
+                ids.iter()
+                    .map(|id| {
+                        features
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (feature_name, start, end))| {
+                                let started = std::time::Instant::now();
+                                let vectors = match last_n {
+                                    Some(n) => db.prefix_seek_last(id, feature_name, *start, *end, n)?,
+                                    None => db.prefix_seek_timed(id, feature_name, *start, *end)?,
+                                };
+                                if let Some(metrics) = &metrics {
+                                    metrics.observe_read(&labels[i], vectors.len(), started.elapsed());
+                                }
+                                if vectors.is_empty() && strict {
+                                    return Err(Status::not_found(format!("No data for id {:?}, feature {:?}", id, feature_name)));
+                                }
+                                Ok(vectors)
+                            })
+                            .collect::<Result<Vec<_>, Status>>()
+                    })
+                    .collect::<Result<Vec<_>, Status>>()
+            })?);
+        }
+
+        let mut rows = Vec::with_capacity(request.ids.len());
+        for task in tasks {
+            rows.extend(task.await.map_err(|e| Status::internal(format!("lookup task failed: {}", e)))??);
+        }
+        Ok((stats, rows))
+    }
+
+    /// Lists the entities of `features` the caller may read, in key order.
+    async fn all_entities(&self, features: Vec<String>, session: Option<Session>) -> Result<Vec<String>, Status> {
+        let db = self.db.clone();
+        spawn_blocking(&self.closing, move || {
+            let mut entities = BTreeSet::new();
+            for feature in &features {
+                entities.extend(catalog::feature_entities(db.as_ref(), feature)?);
+            }
+            // out-of-scope entities are left out rather than denied
+            Ok(entities
+                .into_iter()
+                .filter(|entity| session.as_ref().is_none_or(|s| s.allows(Permission::Read, &[], &[entity])))
+                .collect())
+        })?
+        .await
+        .map_err(|e| Status::internal(format!("entity listing failed: {}", e)))?
+    }
+
+    /// The original layout: one batch per id, each feature's timesteps
+    /// concatenated into a single Float32 (or, when raw, Binary) column.
+    /// A missing feature is a single null.
+    fn flat_batches(request: &TicketRequest, rows: &[Vec<Vectors>]) -> Result<(SchemaRef, Vec<(RecordBatch, Range<usize>)>), Status> {
+        // Raw requests get one encoded value (header + stored elements) per timestep
+        let data_type = if request.raw { DataType::Binary } else { DataType::Float32 };
+        let schema = Arc::new(Schema::new(
+            request.features.iter().map(|(feature_name, _, _)| Field::new(feature_name, data_type.clone(), true)).collect::<Vec<Field>>()
+        ));
+        let mut batches = Vec::new();
+        for (i, columns) in rows.iter().enumerate() {
+            let mut arrays = Vec::new();
+            for vectors in columns {
+                let array: ArrayRef = if vectors.is_empty() {
+                    arrow::array::new_null_array(&data_type, 1)
+                } else if request.raw {
+                    let values = vectors
+                        .iter()
+                        .map(|(_, v)| v.to_bytes(Endianness::Little))
+                        .collect::<blackhole::Result<Vec<_>>>()?;
+                    Arc::new(BinaryArray::from_iter_values(values))
+                } else {
+                    Arc::new(Float32Array::from_iter_values(vectors.iter().flat_map(|(_, v)| v.dequantize())))
+                };
+                arrays.push(array);
+            }
+            let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(|e| Status::internal(e.to_string()))?;
+            batches.push((batch, i..i + 1));
+        }
+        Ok((schema, batches))
+    }
+
+    /// Shape-preserving layout: an `id` column, then per feature a
+    /// `List<FixedSizeList<Float32, dim>>` column (`List<Binary>` when raw)
+    /// holding one entry per timestep, plus a `{feature}_ts` column listing the
+    /// timestamps of those entries. Missing (id, feature) pairs are null in both.
+    /// Ids are grouped into batches in request order, within the batch limits.
+    /// `stats` holds the catalog entry of each requested feature.
+    fn nested_batches(&self, request: &TicketRequest, stats: &[Option<FeatureStats>], rows: &[Vec<Vectors>]) -> Result<(SchemaRef, Vec<(RecordBatch, Range<usize>)>), Status> {
+        let mut fields = vec![Field::new("id", DataType::Utf8, false)];
+        let mut dims = Vec::new();
+        for (i, (feature_name, _, _)) in request.features.iter().enumerate() {
+            let dim = Self::feature_dim(feature_name, stats[i].as_ref(), rows.iter().map(|columns| &columns[i]))?;
+            fields.extend(Self::nested_fields(feature_name, dim, request.raw));
+            if request.missing_mask {
+                fields.push(Field::new(format!("{}_missing", feature_name), DataType::Boolean, false));
+            }
+            dims.push(dim);
+        }
+        let schema = Arc::new(Schema::new(fields));
+
+        let mut batches = Vec::new();
+        for range in self.batch_ranges(rows, request.raw) {
+            let mut arrays: Vec<ArrayRef> = vec![Arc::new(StringArray::from_iter_values(&request.ids[range.clone()]))];
+            for (i, dim) in dims.iter().enumerate() {
+                let entries: Vec<&Vectors> = rows[range.clone()].iter().map(|columns| &columns[i]).collect();
+                arrays.push(Self::vector_list(&entries, *dim, request.raw)?);
+                arrays.push(Self::ts_list(&entries));
+                if request.missing_mask {
+                    arrays.push(Arc::new(BooleanArray::from_iter(entries.iter().map(|v| Some(v.is_empty())))));
+                }
+            }
+            let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(|e| Status::internal(e.to_string()))?;
+            batches.push((batch, range));
+        }
+        Ok((schema, batches))
+    }
+
+    /// Splits `rows` into consecutive ranges within `max_batch_rows` and,
+    /// by a size estimate of the encoded vectors, `max_batch_bytes`.
+    fn batch_ranges(&self, rows: &[Vec<Vectors>], raw: bool) -> Vec<Range<usize>> {
+        let row_bytes = |columns: &Vec<Vectors>| -> usize {
+            columns
+                .iter()
+                .flatten()
+                .map(|(_, v)| {
+                    let value = if raw { VALUE_HEADER_LEN + v.dim() * v.dtype().size() } else { v.dim() * 4 };
+                    value + std::mem::size_of::<u64>()
+                })
+                .sum()
+        };
+        let mut ranges = Vec::new();
+        let (mut start, mut bytes) = (0, 0);
+        for (i, columns) in rows.iter().enumerate() {
+            let size = row_bytes(columns);
+            if i > start && (i - start == self.max_batch_rows || bytes + size > self.max_batch_bytes) {
+                ranges.push(start..i);
+                (start, bytes) = (i, 0);
+            }
+            bytes += size;
+        }
+        if start < rows.len() {
+            ranges.push(start..rows.len());
+        }
+        ranges
+    }
+
+    /// The vector and timestamp fields of one feature in the nested layout.
+    fn nested_fields(feature: &str, dim: i32, raw: bool) -> [Field; 2] {
+        let item = if raw {
+            Field::new("item", DataType::Binary, false)
+        } else {
+            Field::new("item", DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, false)), dim), false)
+        };
+        [
+            Field::new(feature, DataType::List(Arc::new(item)), true),
+            Field::new(format!("{}_ts", feature), DataType::List(Arc::new(Field::new("item", DataType::UInt64, false))), true),
+        ]
+    }
+
+    /// JSON status of one id: `ok`, `partial` or `missing`, with the missing features.
+    fn id_status(id: &str, features: &[(String, u64, u64)], columns: &[Vectors]) -> String {
+        let missing: Vec<String> = features
+            .iter()
+            .zip(columns)
+            .filter(|(_, vectors)| vectors.is_empty())
+            .map(|((name, _, _), _)| json_string(name))
             .collect();
+        let status = match missing.len() {
+            0 => "ok",
+            n if n == features.len() => "missing",
+            _ => "partial",
+        };
+        format!("{{\"id\": {}, \"status\": \"{}\", \"missing\": [{}]}}", json_string(id), status, missing.join(", "))
+    }
 
-        Ok((ids, features))
+    /// Dimension of a feature, from its catalog entry or else from the vectors read.
+    fn feature_dim<'a>(feature: &str, stats: Option<&FeatureStats>, mut vectors: impl Iterator<Item = &'a Vectors>) -> Result<i32, Status> {
+        let dim = match stats {
+            Some(stats) => stats.dim as usize,
+            None => vectors.find_map(|v| v.first()).map(|(_, v)| v.dim()).unwrap_or(0),
+        };
+        i32::try_from(dim).map_err(|_| Status::internal(format!("feature {:?} has dimension {}", feature, dim)))
+    }
+
+    /// One list entry per element of `rows`, each holding that row's vectors;
+    /// empty rows are null.
+    fn vector_list(rows: &[&Vectors], dim: i32, raw: bool) -> Result<ArrayRef, Status> {
+        let offsets = OffsetBuffer::from_lengths(rows.iter().map(|v| v.len()));
+        let vectors = rows.iter().copied().flatten().map(|(_, v)| v);
+        let (item, values): (Field, ArrayRef) = if raw {
+            let values = vectors
+                .map(|v| v.to_bytes(Endianness::Little))
+                .collect::<blackhole::Result<Vec<_>>>()?;
+            (Field::new("item", DataType::Binary, false), Arc::new(BinaryArray::from_iter_values(values)))
+        } else {
+            let mut values = Vec::new();
+            for v in vectors {
+                if v.dim() != dim as usize {
+                    return Err(Status::data_loss(format!("stored vector has dimension {}, expected {}", v.dim(), dim)));
+                }
+                values.extend(v.dequantize());
+            }
+            let element = Arc::new(Field::new("item", DataType::Float32, false));
+            let list = FixedSizeListArray::try_new(element.clone(), dim, Arc::new(Float32Array::from(values)), None)
+                .map_err(|e| Status::internal(e.to_string()))?;
+            (Field::new("item", DataType::FixedSizeList(element, dim), false), Arc::new(list))
+        };
+        let list = ListArray::try_new(Arc::new(item), offsets, values, Self::present(rows))
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Arc::new(list))
+    }
+
+    fn present(rows: &[&Vectors]) -> Option<NullBuffer> {
+        let present = NullBuffer::from_iter(rows.iter().map(|v| !v.is_empty()));
+        (present.null_count() > 0).then_some(present)
+    }
+
+    /// Timestamps matching `vector_list(rows, ..)` entry for entry.
+    fn ts_list(rows: &[&Vectors]) -> ArrayRef {
+        let offsets = OffsetBuffer::from_lengths(rows.iter().map(|v| v.len()));
+        let values = UInt64Array::from_iter_values(rows.iter().copied().flatten().map(|(ts, _)| *ts));
+        Arc::new(ListArray::new(Arc::new(Field::new("item", DataType::UInt64, false)), offsets, Arc::new(values), Self::present(rows)))
+    }
+
+    /// Reads a descriptor naming a feature: either a path `[feature, id...]`
+    /// or a command holding the feature name as UTF-8.
+    fn decode_descriptor(descriptor: &FlightDescriptor) -> Result<(String, Vec<String>), Status> {
+        match descriptor.r#type() {
+            DescriptorType::Path => {
+                let (feature, ids) = descriptor
+                    .path
+                    .split_first()
+                    .ok_or_else(|| Status::invalid_argument("descriptor path must start with a feature name"))?;
+                Ok((feature.clone(), ids.to_vec()))
+            }
+            DescriptorType::Cmd => {
+                let feature = std::str::from_utf8(&descriptor.cmd)
+                    .map_err(|e| Status::invalid_argument(format!("descriptor cmd is not a UTF-8 feature name: {}", e)))?;
+                Ok((feature.to_string(), Vec::new()))
+            }
+            DescriptorType::Unknown => Err(Status::invalid_argument("descriptor type must be PATH or CMD")),
+        }
+    }
+
+    fn feature_stats(&self, feature: &str) -> Result<FeatureStats, Status> {
+        catalog::feature_stats(self.db.as_ref(), feature)?
+            .ok_or_else(|| Status::not_found(format!("feature {:?} is not in the catalog", feature)))
+    }
+
+    /// Schema of a `do_get` on one feature, with the catalog entry as field metadata.
+    fn feature_schema(stats: &FeatureStats) -> Schema {
+        let metadata = HashMap::from([
+            ("dtype".to_string(), stats.dtype.to_string()),
+            ("dim".to_string(), stats.dim.to_string()),
+            ("entity_count".to_string(), stats.entity_count.to_string()),
+            ("key_count".to_string(), stats.key_count.to_string()),
+            ("min_ts".to_string(), stats.min_ts.to_string()),
+            ("max_ts".to_string(), stats.max_ts.to_string()),
+        ]);
+        let [values, timestamps] = Self::nested_fields(&stats.name, stats.dim as i32, false);
+        Schema::new(vec![Field::new("id", DataType::Utf8, false), values.with_metadata(metadata), timestamps])
+    }
+
+    /// Builds a ticket in the layout `decode_ticket` reads, covering `[start, end]`
+    /// of one feature. Without `ids`, the ticket asks for all entities.
+    fn encode_ticket(ids: &[String], feature: &str, start: u64, end: u64) -> Result<Ticket, Status> {
+        let all_entities = ids.is_empty();
+        let id_field = Arc::new(Field::new("item", DataType::Utf8, true));
+        let ids = ListArray::new(
+            id_field.clone(),
+            OffsetBuffer::from_lengths([ids.len()]),
+            Arc::new(StringArray::from_iter_values(ids)),
+            None,
+        );
+        let feature_fields = Fields::from(vec![
+            Field::new("name", DataType::Utf8, false),
+            Field::new("start", DataType::UInt64, true),
+            Field::new("end", DataType::UInt64, true),
+        ]);
+        let feature = StructArray::new(
+            feature_fields.clone(),
+            vec![
+                Arc::new(StringArray::from(vec![feature])),
+                Arc::new(UInt64Array::from(vec![start])),
+                Arc::new(UInt64Array::from(vec![end])),
+            ],
+            None,
+        );
+        let feature_field = Arc::new(Field::new("item", DataType::Struct(feature_fields), true));
+        let features = ListArray::new(feature_field.clone(), OffsetBuffer::from_lengths([1]), Arc::new(feature), None);
+        let mut fields = vec![
+            Field::new("ids", DataType::List(id_field), true),
+            Field::new("features", DataType::List(feature_field), true),
+        ];
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(ids), Arc::new(features)];
+        if all_entities {
+            fields.push(Field::new("all_entities", DataType::Boolean, true));
+            columns.push(Arc::new(BooleanArray::from(vec![true])));
+        }
+        let request = StructArray::new(Fields::from(fields), columns, None);
+        let batch = RecordBatch::try_from_iter([("request", Arc::new(request) as ArrayRef)])
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let mut buf = Vec::new();
+        let mut writer = StreamWriter::try_new(&mut buf, &batch.schema()).map_err(|e| Status::internal(e.to_string()))?;
+        writer.write(&batch).map_err(|e| Status::internal(e.to_string()))?;
+        writer.finish().map_err(|e| Status::internal(e.to_string()))?;
+        drop(writer);
+        Ok(Ticket::new(buf))
+    }
+
+    /// Runs one admin action, returning its JSON result. Compactions, flushes
+    /// and checkpoints block, so callers run this off the async workers.
+    fn run_action(db: &dyn DbInterface, checkpoint_root: Option<&Path>, action: &Action) -> Result<String, Status> {
+        let body = std::str::from_utf8(&action.body)
+            .map_err(|e| Status::invalid_argument(format!("{} body is not UTF-8: {}", action.r#type, e)))?;
+        match action.r#type.as_str() {
+            "stats" => {
+                let fields = db.stats()?
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", json_string(name), json_string(value)))
+                    .collect::<Vec<_>>();
+                Ok(format!("{{{}}}", fields.join(", ")))
+            }
+            "compact_range" => {
+                let (start, end) = body.split_once(':').unwrap_or((body, ""));
+                let start = decode_hex(start)?;
+                let end = decode_hex(end)?;
+                db.compact_range(start.as_deref(), end.as_deref())?;
+                Ok("{\"compacted\": true}".to_string())
+            }
+            "flush" => {
+                db.flush()?;
+                Ok("{\"flushed\": true}".to_string())
+            }
+            "checkpoint" => {
+                let dir = checkpoint_dir(checkpoint_root, body)?;
+                db.checkpoint(&dir)?;
+                Ok(format!("{{\"checkpoint\": {}}}", json_string(body)))
+            }
+            "reader_check" => Ok(format!("{{\"cleared\": {}}}", db.reader_check()?)),
+            other => Err(Status::invalid_argument(format!("unknown action {:?}", other))),
+        }
+    }
+
+    /// Describes the feature named by the request's descriptor, if the caller may read it.
+    fn flight_info(&self, request: &Request<FlightDescriptor>) -> Result<FlightInfo, Status> {
+        let (feature, ids) = Self::decode_descriptor(request.get_ref())?;
+        let entities: Vec<&str> = ids.iter().map(String::as_str).collect();
+        self.require_read(request, &[&feature], &entities)?;
+        let stats = self.feature_stats(&feature)?;
+        self.feature_info(request.get_ref().clone(), &stats, ids)
+    }
+
+    /// Schema, counts and a ticket over the whole time range of one feature.
+    /// Without `ids`, the ticket covers every entity of the feature, resolved
+    /// by `do_get` within the caller's scope.
+    fn feature_info(&self, descriptor: FlightDescriptor, stats: &FeatureStats, ids: Vec<String>) -> Result<FlightInfo, Status> {
+        let feature = &stats.name;
+        // the nested layout returns one row per entity
+        let total_records = if ids.is_empty() { stats.entity_count } else { ids.len() as u64 } as i64;
+        let ticket = Self::encode_ticket(&ids, feature, stats.min_ts, stats.max_ts)?;
+        FlightInfo::new()
+            .try_with_schema(&Self::feature_schema(stats))
+            .map_err(|e| Status::internal(e.to_string()))
+            .map(|info| {
+                info.with_descriptor(descriptor)
+                    .with_endpoint(FlightEndpoint::new().with_ticket(ticket))
+                    .with_total_records(total_records)
+                    .with_ordered(true)
+            })
     }
 }
 #[tonic::async_trait]
@@ -115,9 +780,16 @@ impl FlightService for FlightDbServer {
     
     async fn get_schema(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<SchemaResult>, Status> {
-        Err(Status::unimplemented("get_schema is not implemented"))
+        let (feature, ids) = Self::decode_descriptor(request.get_ref())?;
+        let entities: Vec<&str> = ids.iter().map(String::as_str).collect();
+        self.require_read(&request, &[&feature], &entities)?;
+        let schema = Self::feature_schema(&self.feature_stats(&feature)?);
+        let result = SchemaAsIpc::new(&schema, &IpcWriteOptions::default())
+            .try_into()
+            .map_err(|e: arrow::error::ArrowError| Status::internal(e.to_string()))?;
+        Ok(Response::new(result))
     }
 
     async fn do_exchange(
@@ -131,118 +803,409 @@ impl FlightService for FlightDbServer {
 
     async fn handshake(
         &self,
-        _request: Request<Streaming<HandshakeRequest>>,
+        request: Request<Streaming<HandshakeRequest>>,
     ) -> Result<Response<Self::HandshakeStream>, Status> {
-        unimplemented!()
+        // Without credentials configured there is nothing to negotiate
+        let Some(auth) = &self.auth else {
+            let output = stream::once(async { Ok(HandshakeResponse::default()) });
+            return Ok(Response::new(Box::pin(output)));
+        };
+        let token = auth.handshake(request.metadata())?;
+        let header = auth::bearer(&token)?;
+        let output = stream::once(async move {
+            Ok(HandshakeResponse { protocol_version: 0, payload: Bytes::from(token) })
+        });
+        let mut response = Response::new(Box::pin(output) as Self::HandshakeStream);
+        response.metadata_mut().insert("authorization", header);
+        Ok(response)
     }
 
     async fn list_flights(
         &self,
-        _request: Request<Criteria>,
+        request: Request<Criteria>,
     ) -> Result<Response<Self::ListFlightsStream>, Status> {
-        // Return empty list as we don't support listing
-        let output = futures::stream::empty();
-        Ok(Response::new(Box::pin(output)))
+        let session = self.require_read(&request, &[], &[])?;
+        // A non-empty expression keeps features whose name contains it
+        let expression = request.into_inner().expression;
+        let filter = std::str::from_utf8(&expression)
+            .map_err(|e| Status::invalid_argument(format!("criteria expression is not UTF-8: {}", e)))?;
+        let infos = catalog::list_features(self.db.as_ref())?
+            .into_iter()
+            .filter(|stats| stats.name.contains(filter))
+            // features outside the caller's read scope are left out rather than denied
+            .filter(|stats| session.as_ref().is_none_or(|s| s.allows(Permission::Read, &[&stats.name], &[])))
+            // only catalog entries are read; entities are resolved when a ticket is redeemed
+            .map(|stats| {
+                let descriptor = FlightDescriptor::new_path(vec![stats.name.clone()]);
+                self.feature_info(descriptor, &stats, Vec::new())
+            })
+            .collect::<Vec<_>>();
+        Ok(Response::new(Box::pin(stream::iter(infos))))
     }
 
     async fn do_get(
         &self,
         request: Request<Ticket>,
     ) -> Result<Response<Self::DoGetStream>, Status> {
-        let ticket = request.into_inner().ticket;
-        let (ids, features) = self.decode_ticket(&ticket)?;
-        // assert_eq!(features.len(), 1);
-        // let (feature_name, start, end) = &features[0];
-        let schema = Arc::new(Schema::new(
-            features.iter().map(|(feature_name, _, _)| Field::new(feature_name, DataType::Float32, false)).collect::<Vec<Field>>()
-        ));
-
-        // println!("Features: {:?}", features);
-        // println!("IDs: {:?}", ids);
-        
-        // Collect all values for each ID using prefix seek
-        let mut batches = Vec::new();
-        for id in ids {
-            let mut arrays = Vec::new();
-            for (feature_name, start, end) in &features {
-                let prefix = if feature_name.is_empty() { &id } else { &format!("{}.{}", id, feature_name) };
-                let values = self.db.prefix_seek(prefix, start.unwrap() as u16, end.unwrap() as u16)
-                    .map_err(|e| Status::internal(e.to_string()))?;
-            
-                if values.is_empty() {
-                    return Err(Status::not_found("No matching data found in database"));
-                }
-
-                arrays.push(std::sync::Arc::new(Float32Array::from(values)) as Arc<dyn Array>);
-            }
-            let batch = RecordBatch::try_new(
-                schema.clone(),
-                arrays,
-            ).map_err(|e| Status::internal(e.to_string()))?;
-        
-            batches.push(batch);
+        let mut ticket = self.decode_ticket(&request.get_ref().ticket)?;
+        let names: Vec<&str> = ticket.features.iter().map(|(name, _, _)| name.as_str()).collect();
+        let entities: Vec<&str> = ticket.ids.iter().map(String::as_str).collect();
+        let session = self.require_read(&request, &names, &entities)?;
+        if ticket.all_entities {
+            let features = ticket.features.iter().map(|(name, _, _)| name.clone()).collect();
+            ticket.ids = self.all_entities(features, session).await?;
         }
+        let request = ticket;
+        let (stats, rows) = self.lookup(&request).await?;
+
+        let (schema, batches) = if request.flat {
+            Self::flat_batches(&request, &rows)?
+        } else {
+            self.nested_batches(&request, &stats, &rows)?
+        };
+        // each batch carries the statuses of its ids
+        let (batches, metadata): (Vec<_>, Vec<_>) = batches
+            .into_iter()
+            .map(|(batch, range)| {
+                let statuses = range
+                    .map(|i| Self::id_status(&request.ids[i], &request.features, &rows[i]))
+                    .collect::<Vec<_>>();
+                let rows = batch.num_rows();
+                (batch, (rows, Bytes::from(format!("[{}]", statuses.join(", ")))))
+            })
+            .unzip();
 
         let stream = stream::iter(batches).map(Ok);
         let fd = FlightDataEncoderBuilder::new()
             .with_schema(schema).build(stream)
             .map_err(|e| Status::internal(e.to_string()));
+        let metrics = self.metrics.clone();
+        let fd = with_batch_metadata(fd, metadata).inspect_ok(move |data| {
+            if let Some(metrics) = &metrics {
+                metrics.observe_bytes_returned(data.data_header.len() + data.data_body.len() + data.app_metadata.len());
+            }
+        });
         Ok(Response::new(Box::pin(fd)))
     }
 
     async fn do_put(
         &self,
-        _request: Request<Streaming<FlightData>>,
+        request: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoPutStream>, Status> {
-        // Return not implemented as we don't support putting data
-        Err(Status::unimplemented("do_put not implemented"))
+        self.require(&request, Permission::Write)?;
+        if self.db.is_read_only() {
+            return Err(Status::failed_precondition("do_put is not available on a read-only backend"));
+        }
+        let session = request.extensions().get::<Session>().cloned();
+        let db = self.db.clone();
+        let bucketing = self.bucketing;
+        let closing = self.closing.clone();
+        let batches = FlightRecordBatchStream::new_from_flight_data(
+            request.into_inner().map_err(FlightError::from),
+        );
+        // Each incoming batch is committed atomically, on the blocking pool, and
+        // acknowledged with its row count
+        let results = batches.then(move |batch| {
+            let session = session.clone();
+            let db = db.clone();
+            let closing = closing.clone();
+            async move {
+                let batch = batch.map_err(|e| Status::invalid_argument(e.to_string()))?;
+                if let Some(session) = &session {
+                    Self::check_put_scope(session, &batch)?;
+                }
+                let rows = spawn_blocking(&closing, move || {
+                    let items = Self::encode_put_batch(db.as_ref(), bucketing, &batch)?;
+                    db.batch_put(&items)?;
+                    Ok::<_, Status>(items.len())
+                })?
+                .await
+                .map_err(|e| Status::internal(format!("put task failed: {}", e)))??;
+                Ok(PutResult { app_metadata: Bytes::from(format!("{{\"rows\": {}}}", rows)) })
+            }
+        });
+        Ok(Response::new(Box::pin(results)))
     }
 
     async fn do_action(
         &self,
-        _request: Request<Action>,
+        request: Request<Action>,
     ) -> Result<Response<Self::DoActionStream>, Status> {
-        // Return not implemented as we don't support actions
-        Err(Status::unimplemented("do_action not implemented"))
+        self.require(&request, Permission::Admin)?;
+        let db = self.db.clone();
+        let checkpoint_root = self.checkpoint_root.clone();
+        let action = request.into_inner();
+        let body = spawn_blocking(&self.closing, move || {
+            Self::run_action(db.as_ref(), checkpoint_root.as_deref(), &action)
+        })?
+            .await
+            .map_err(|e| Status::internal(format!("admin action failed: {}", e)))??;
+        let result = arrow_flight::Result { body: Bytes::from(body) };
+        Ok(Response::new(Box::pin(stream::once(async { Ok(result) }))))
     }
 
     async fn list_actions(
         &self,
         _request: Request<Empty>,
     ) -> Result<Response<Self::ListActionsStream>, Status> {
-        // Return empty list as we don't support actions
-        let output = futures::stream::empty();
-        Ok(Response::new(Box::pin(output)))
+        let actions = ADMIN_ACTIONS.iter().map(|(name, description)| {
+            Ok(ActionType { r#type: name.to_string(), description: description.to_string() })
+        });
+        Ok(Response::new(Box::pin(stream::iter(actions))))
     }
 
     async fn get_flight_info(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<FlightInfo>, Status> {
-        // Return not implemented as we don't support flight info
-        Err(Status::unimplemented("get_flight_info not implemented"))
+        Ok(Response::new(self.flight_info(&request)?))
     }
 
     async fn poll_flight_info(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<PollInfo>, Status> {
-        unimplemented!()
+        // the catalog is always current, so every query completes on the first poll
+        let info = self.flight_info(&request)?;
+        let poll = PollInfo::new()
+            .with_info(info)
+            .try_with_progress(1.0)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(poll))
+    }
+}
+
+/// Sets `app_metadata` on the encoded messages of each batch. `batches` gives
+/// the row count and metadata of every batch in encoding order; the encoder
+/// may split a batch over several messages, which then all carry its metadata.
+fn with_batch_metadata(
+    encoded: impl Stream<Item = Result<FlightData, Status>> + Send + 'static,
+    batches: impl IntoIterator<Item = (usize, Bytes)>,
+) -> impl Stream<Item = Result<FlightData, Status>> + Send + 'static {
+    // empty batches are not encoded at all
+    let mut pending: VecDeque<(usize, Bytes)> = batches.into_iter().filter(|(rows, _)| *rows > 0).collect();
+    encoded.map(move |data| {
+        let mut data = data?;
+        let rows = arrow::ipc::root_as_message(&data.data_header)
+            .ok()
+            .and_then(|message| message.header_as_record_batch())
+            .map(|batch| batch.length() as usize);
+        if let (Some(rows), Some((remaining, metadata))) = (rows, pending.front_mut()) {
+            data.app_metadata = metadata.clone();
+            *remaining = remaining.saturating_sub(rows);
+            if *remaining == 0 {
+                pending.pop_front();
+            }
+        }
+        Ok(data)
+    })
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
     }
+    out.push('"');
+    out
 }
 
+/// Resolves the `checkpoint` action body to a directory under `root`. The body
+/// must be a plain relative path, so clients cannot write outside the root.
+fn checkpoint_dir(root: Option<&Path>, body: &str) -> Result<PathBuf, Status> {
+    let root = root.ok_or_else(|| Status::failed_precondition("checkpoints are disabled; set checkpoint_dir"))?;
+    let relative = Path::new(body);
+    if body.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(Status::invalid_argument(format!(
+            "checkpoint target {:?} must be a relative path without \"..\"", body
+        )));
+    }
+    Ok(root.join(relative))
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Starting Flight server...");
-    
-    let server = FlightDbServer::new(DatabaseType::RocksDB);
-    
-    let addr = "[::1]:50051".parse().unwrap();
-    tonic::transport::Server::builder()
-        .add_service(FlightServiceServer::new(server))
-        .serve(addr)
-        .await?;
-    
-    Ok(())
-} 
\ No newline at end of file
+/// Starts `f` on the blocking pool unless shutdown has begun, so no new
+/// backend work starts once the backend is about to be closed.
+fn spawn_blocking<R: Send + 'static>(closing: &AtomicBool, f: impl FnOnce() -> R + Send + 'static) -> Result<JoinHandle<R>, Status> {
+    if closing.load(Ordering::Acquire) {
+        return Err(Status::unavailable("server is shutting down"));
+    }
+    Ok(tokio::task::spawn_blocking(f))
+}
+
+/// Decodes a hex key bound; an empty string means unbounded.
+fn decode_hex(s: &str) -> Result<Option<Vec<u8>>, Status> {
+    if s.is_empty() {
+        return Ok(None);
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(Status::invalid_argument(format!("odd-length hex key {:?}", s)));
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| Status::invalid_argument(format!("invalid hex key {:?}", s)))
+        })
+        .collect::<Result<Vec<u8>, Status>>()
+        .map(Some)
+}
+
+fn main() {
+    let mut config = match ServerConfig::load(Cli::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("training: {}", e);
+            std::process::exit(2);
+        }
+    };
+    // validated above, so the filter parses
+    tracing_subscriber::fmt().with_env_filter(config.log_filter().unwrap()).init();
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = config.worker_threads {
+        runtime.worker_threads(threads);
+    }
+    let runtime = match runtime.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let server = match build_server(&mut config) {
+        Ok(server) => server,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let db = server.db.clone();
+    let closing = server.closing();
+    let drain_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+
+    let result = runtime.block_on(serve(config, server));
+    // Blocking tasks (lookups, puts, admin actions) may outlive the streams
+    // that started them; give them what is left of the drain timeout and then
+    // abandon them rather than waiting on the runtime's unbounded drop.
+    closing.store(true, Ordering::Release);
+    let deadline = match &result {
+        Ok(deadline) => *deadline,
+        Err(_) => Instant::now() + drain_timeout,
+    };
+    runtime.shutdown_timeout(deadline.saturating_duration_since(Instant::now()));
+
+    let mut failed = false;
+    if let Err(e) = result {
+        tracing::error!("{}", e);
+        failed = true;
+    }
+    match db.close() {
+        Ok(()) => tracing::info!("backend closed"),
+        Err(e) => {
+            tracing::error!("failed to close the backend: {}", e);
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Opens the backend and sets up the Flight service described by `config`.
+fn build_server(config: &mut ServerConfig) -> Result<FlightDbServer, Box<dyn std::error::Error>> {
+    tracing::info!(listen = %config.listen, backend = ?config.backend, path = %config.storage.path.display(), "starting Flight server");
+
+    // serve read-only unless the config explicitly asks for write access
+    config.storage.read_only.get_or_insert(true);
+    let mut server = FlightDbServer::new(config.backend.database_type(), &config.storage)?
+        .with_batch_limits(config.max_batch_rows, config.max_batch_bytes);
+    if let Some(bucketing) = config.bucketing.time_bucketing() {
+        server = server.with_bucketing(bucketing);
+    }
+    if let Some(tasks) = config.lookup_parallelism {
+        server = server.with_lookup_parallelism(tasks);
+    }
+    if let Some(root) = &config.checkpoint_dir {
+        server = server.with_checkpoint_root(root);
+    }
+    let metrics = Metrics::new(server.db.clone())?;
+    server = server.with_metrics(metrics);
+
+    // Optional credentials file; without it every client is trusted
+    if let Some(path) = &config.credentials {
+        server = server.with_auth(Authenticator::new(Credentials::from_toml_file(path)?));
+    }
+    Ok(server)
+}
+
+/// Serves until SIGINT/SIGTERM and the drain that follows. Returns the
+/// deadline by which all remaining work must stop.
+async fn serve(config: ServerConfig, server: FlightDbServer) -> Result<Instant, Box<dyn std::error::Error>> {
+    let metrics = server.metrics.clone().expect("build_server sets up metrics");
+    let auth = server.auth.clone();
+    let interceptor = move |request| match &auth {
+        Some(auth) => auth.intercept(request),
+        None => Ok(request),
+    };
+    let closing = server.closing();
+    let service = FlightServiceServer::new(server)
+        .max_decoding_message_size(config.max_message_size)
+        .max_encoding_message_size(config.max_message_size);
+
+    // On SIGINT/SIGTERM tonic stops accepting connections and waits for the
+    // open ones to finish; streams still running after the timeout are cut off.
+    // New blocking work is refused from the signal on.
+    let drain_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let (deadline_tx, mut deadline_rx) = tokio::sync::watch::channel(None);
+    let shutdown = async move {
+        shutdown_signal().await;
+        tracing::info!("shutting down, draining open streams");
+        closing.store(true, Ordering::Release);
+        let _ = deadline_tx.send(Some(Instant::now() + drain_timeout));
+    };
+
+    let listener = tokio::net::TcpListener::bind(config.metrics_listen).await?;
+    tracing::info!(listen = %config.metrics_listen, "serving /metrics");
+    let render = metrics.clone();
+    let app = axum::Router::new().route("/metrics", axum::routing::get(move || async move { render.render() }));
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("metrics endpoint failed: {}", e);
+        }
+    });
+
+    let server = tonic::transport::Server::builder()
+        .add_service(Metered::new(InterceptedService::new(service, interceptor), metrics))
+        .serve_with_shutdown(config.listen, shutdown);
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            let deadline = match deadline_rx.wait_for(Option::is_some).await {
+                Ok(deadline) => (*deadline).expect("waited for a deadline"),
+                Err(_) => return std::future::pending().await,
+            };
+            tokio::time::sleep_until(deadline.into()).await;
+        } => tracing::warn!("streams still open after {:?}, closing anyway", drain_timeout),
+    }
+    let deadline = *deadline_rx.borrow();
+    Ok(deadline.unwrap_or_else(|| Instant::now() + drain_timeout))
+}
+
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to install the SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
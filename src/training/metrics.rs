@@ -0,0 +1,195 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use prometheus::{Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
+use tonic::codegen::{http, BoxFuture, Service};
+use tonic::server::NamedService;
+use tonic::Code;
+
+use blackhole::{DbInterface, FeatureStats};
+
+/// Methods of the Flight service; other paths are counted as `unknown`.
+const FLIGHT_RPCS: [&str; 10] = [
+    "Handshake", "ListFlights", "GetFlightInfo", "PollFlightInfo", "GetSchema",
+    "DoGet", "DoPut", "DoExchange", "DoAction", "ListActions",
+];
+
+/// Feature label for reads of names that are not in the catalog.
+const OTHER_FEATURE: &str = "other";
+
+/// Prometheus metrics of the Flight server, rendered in the text format on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_seconds: HistogramVec,
+    feature_reads: IntCounterVec,
+    feature_read_seconds: HistogramVec,
+    keys_read: IntCounterVec,
+    not_found: IntCounterVec,
+    bytes_returned: IntCounter,
+    backend: GaugeVec,
+    db: Arc<dyn DbInterface>,
+}
+
+impl Metrics {
+    pub fn new(db: Arc<dyn DbInterface>) -> prometheus::Result<Arc<Self>> {
+        let registry = Registry::new_custom(Some("blackhole".to_string()), None)?;
+        let requests = IntCounterVec::new(
+            Opts::new("flight_requests_total", "Flight RPCs by method and gRPC status"),
+            &["rpc", "code"],
+        )?;
+        let request_seconds = HistogramVec::new(
+            HistogramOpts::new("flight_request_duration_seconds", "Time until a Flight RPC returns its response; streams are timed until they start"),
+            &["rpc"],
+        )?;
+        let feature_reads = IntCounterVec::new(
+            Opts::new("feature_reads_total", "(id, feature) range reads served by do_get"),
+            &["feature"],
+        )?;
+        let feature_read_seconds = HistogramVec::new(
+            HistogramOpts::new("feature_read_duration_seconds", "Backend time of one (id, feature) range read"),
+            &["feature"],
+        )?;
+        let keys_read = IntCounterVec::new(
+            Opts::new("feature_keys_read_total", "Vectors read from the backend"),
+            &["feature"],
+        )?;
+        let not_found = IntCounterVec::new(
+            Opts::new("feature_not_found_total", "(id, feature) range reads that found nothing"),
+            &["feature"],
+        )?;
+        let bytes_returned = IntCounter::new("flight_bytes_returned_total", "Encoded Flight data sent by do_get")?;
+        let backend = GaugeVec::new(
+            Opts::new("backend_stat", "Numeric backend counters, as reported by the stats admin action"),
+            &["stat"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_seconds.clone()))?;
+        registry.register(Box::new(feature_reads.clone()))?;
+        registry.register(Box::new(feature_read_seconds.clone()))?;
+        registry.register(Box::new(keys_read.clone()))?;
+        registry.register(Box::new(not_found.clone()))?;
+        registry.register(Box::new(bytes_returned.clone()))?;
+        registry.register(Box::new(backend.clone()))?;
+        Ok(Arc::new(Self {
+            registry,
+            requests,
+            request_seconds,
+            feature_reads,
+            feature_read_seconds,
+            keys_read,
+            not_found,
+            bytes_returned,
+            backend,
+            db,
+        }))
+    }
+
+    fn observe_request(&self, rpc: &str, code: Code, elapsed: Duration) {
+        self.requests.with_label_values(&[rpc, &format!("{:?}", code)]).inc();
+        self.request_seconds.with_label_values(&[rpc]).observe(elapsed.as_secs_f64());
+    }
+
+    /// The label to record reads of `feature` under, given its catalog entry.
+    /// Names come from client tickets, so only catalog features get a label
+    /// of their own.
+    pub fn feature_label<'a>(feature: &'a str, stats: Option<&FeatureStats>) -> &'a str {
+        match stats {
+            Some(_) => feature,
+            None => OTHER_FEATURE,
+        }
+    }
+
+    /// Records one (id, feature) range read that returned `keys` vectors;
+    /// `feature` is a label from `feature_label`.
+    pub fn observe_read(&self, feature: &str, keys: usize, elapsed: Duration) {
+        self.feature_reads.with_label_values(&[feature]).inc();
+        self.feature_read_seconds.with_label_values(&[feature]).observe(elapsed.as_secs_f64());
+        self.keys_read.with_label_values(&[feature]).inc_by(keys as u64);
+        if keys == 0 {
+            self.not_found.with_label_values(&[feature]).inc();
+        }
+    }
+
+    pub fn observe_bytes_returned(&self, bytes: usize) {
+        self.bytes_returned.inc_by(bytes as u64);
+    }
+
+    /// Refreshes the backend gauges and encodes every metric in the text format.
+    pub fn render(&self) -> String {
+        self.backend.reset();
+        match self.db.stats() {
+            Ok(stats) => {
+                for (name, value) in stats {
+                    // booleans such as read_only become 0 or 1
+                    let value = value.parse::<f64>().ok().or_else(|| value.parse::<bool>().ok().map(f64::from));
+                    if let Some(value) = value {
+                        self.backend.with_label_values(&[&name]).set(value);
+                    }
+                }
+            }
+            Err(e) => tracing::debug!("backend stats unavailable: {}", e),
+        }
+        let mut out = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut out) {
+            tracing::warn!("failed to encode metrics: {}", e);
+        }
+        String::from_utf8(out).unwrap_or_default()
+    }
+}
+
+/// Wraps a gRPC service to count and time every call by method and status.
+/// Handler errors travel in the response headers; a status sent later in the
+/// trailers of a stream is not seen here.
+#[derive(Clone)]
+pub struct Metered<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl<S: NamedService> NamedService for Metered<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B, R> Service<http::Request<B>> for Metered<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // paths look like /arrow.flight.protocol.FlightService/DoGet
+        let method = request.uri().path().rsplit('/').next().unwrap_or_default();
+        let rpc = FLIGHT_RPCS.iter().copied().find(|rpc| *rpc == method).unwrap_or("unknown");
+        let metrics = self.metrics.clone();
+        let start = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            let code = match &response {
+                Ok(response) => response
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|status| status.to_str().ok()?.parse::<i32>().ok())
+                    .map_or(Code::Ok, Code::from),
+                Err(_) => Code::Internal,
+            };
+            metrics.observe_request(rpc, code, start.elapsed());
+            response
+        })
+    }
+}